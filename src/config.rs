@@ -1,45 +1,186 @@
 use std::path::Path;
 use std::path::PathBuf;
+use std::time::Duration;
 
+use serde::{Deserialize, Serialize};
+
+use crate::data::data_source::watch::WatchEventKind;
+use crate::data::data_source::watch::DEFAULT_WATCH_EVENTS;
 use crate::error::ErrorType;
 use crate::error::FsmError;
+use crate::exclude::ExcludeSet;
+use crate::filter::CollisionStrategy;
+use crate::filter::DuplicateStrategy;
+use crate::schedule::SweepSchedule;
+use crate::stability::DEFAULT_TEMPORARY_SUFFIXES;
+
+const DEFAULT_STABILITY_WINDOW: Duration = Duration::from_secs(2);
+pub const DEFAULT_MOVE_WORKERS: usize = 4;
+pub const DEFAULT_MOVE_QUEUE_SIZE: usize = 1024;
+
+/**
+What to do with directories found in a watch root: `descend` picks up the files inside them (down to
+the root's `max_depth`), `move` moves the whole directory as a unit and `skip` leaves it alone.
+*/
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DirectoryHandling {
+    #[default]
+    Descend,
+    Move,
+    Skip,
+}
+
+/**
+A watched directory, along with the managed directory its files are sorted into.
+*/
+#[derive(Debug, Clone)]
+pub struct WatchRoot {
+    path: PathBuf,
+    recursive: bool,
+    managed_path: PathBuf,
+    max_depth: Option<usize>,
+    directory_handling: DirectoryHandling,
+    schedule: Option<SweepSchedule>,
+}
+
+impl WatchRoot {
+    pub fn new<P, Q>(path: P, recursive: bool, managed_path: Q) -> Self
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        Self {
+            path: path.as_ref().to_owned(),
+            recursive,
+            managed_path: managed_path.as_ref().to_owned(),
+            max_depth: None,
+            directory_handling: DirectoryHandling::default(),
+            schedule: None,
+        }
+    }
+    pub fn set_max_depth(&mut self, max_depth: Option<usize>) {
+        self.max_depth = max_depth;
+    }
+    pub fn set_directory_handling(&mut self, directory_handling: DirectoryHandling) {
+        self.directory_handling = directory_handling;
+    }
+    pub fn get_path(&self) -> &PathBuf {
+        &self.path
+    }
+    pub fn is_recursive(&self) -> bool {
+        self.recursive
+    }
+    pub fn get_managed_path(&self) -> &PathBuf {
+        &self.managed_path
+    }
+    pub fn set_schedule(&mut self, schedule: Option<SweepSchedule>) {
+        self.schedule = schedule;
+    }
+    pub fn get_schedule(&self) -> Option<&SweepSchedule> {
+        self.schedule.as_ref()
+    }
+    pub fn get_directory_handling(&self) -> DirectoryHandling {
+        self.directory_handling
+    }
+    /**
+    How many directories deep files are picked up, where 0 is the top level only and `None` means
+    there is no limit.
+    */
+    pub fn get_max_depth(&self) -> Option<usize> {
+        match self.recursive && self.directory_handling == DirectoryHandling::Descend {
+            true => self.max_depth,
+            false => Some(0),
+        }
+    }
+    /**
+    Returns how many directories below the root the path is, if it is inside the root.
+    */
+    pub fn get_depth(&self, path: &Path) -> Option<usize> {
+        path.strip_prefix(&self.path)
+            .ok()
+            .map(|relative_path| relative_path.components().count().saturating_sub(1))
+    }
+    pub fn is_within_depth(&self, path: &Path) -> bool {
+        match self.get_depth(path) {
+            None => false,
+            Some(depth) => self
+                .get_max_depth()
+                .is_none_or(|max_depth| depth <= max_depth),
+        }
+    }
+}
+
+/**
+Returns the innermost of the watch roots containing the given path.
+*/
+pub fn find_watch_root<'a>(watch_roots: &'a [WatchRoot], path: &Path) -> Option<&'a WatchRoot> {
+    watch_roots
+        .iter()
+        .filter(|watch_root| path.starts_with(watch_root.get_path()))
+        .max_by_key(|watch_root| watch_root.get_path().components().count())
+}
+
 /**
 Struct to manage the various system properties (e.g. base path, etc.)
 */
 #[derive(Debug)]
 pub struct ConfigManager {
-    base_path_to_watch: PathBuf,
+    watch_roots: Vec<WatchRoot>,
     base_path_to_manage: PathBuf,
-    overwrite_on_move: bool,
+    collision_strategy: CollisionStrategy,
+    duplicate_strategy: DuplicateStrategy,
+    dry_run: bool,
+    stability_window: Duration,
+    temporary_suffixes: Vec<String>,
+    watch_events: Vec<WatchEventKind>,
+    exclude: ExcludeSet,
+    schedules: Vec<SweepSchedule>,
+    move_workers: usize,
+    move_queue_size: usize,
 }
 
 impl ConfigManager {
-    pub fn new<P>(base_path_to_watch: P, base_path_to_manage: P, overwrite_on_move: bool) -> Self
+    pub fn new<P>(
+        watch_roots: Vec<WatchRoot>,
+        base_path_to_manage: P,
+        collision_strategy: CollisionStrategy,
+        duplicate_strategy: DuplicateStrategy,
+        dry_run: bool,
+    ) -> Self
     where
         P: AsRef<Path>,
     {
         Self {
-            base_path_to_watch: base_path_to_watch.as_ref().to_owned(),
+            watch_roots,
             base_path_to_manage: base_path_to_manage.as_ref().to_owned(),
-            overwrite_on_move,
+            collision_strategy,
+            duplicate_strategy,
+            dry_run,
+            stability_window: DEFAULT_STABILITY_WINDOW,
+            temporary_suffixes: DEFAULT_TEMPORARY_SUFFIXES
+                .iter()
+                .map(|suffix| suffix.to_string())
+                .collect(),
+            watch_events: DEFAULT_WATCH_EVENTS.to_vec(),
+            exclude: ExcludeSet::default(),
+            schedules: vec![],
+            move_workers: DEFAULT_MOVE_WORKERS,
+            move_queue_size: DEFAULT_MOVE_QUEUE_SIZE,
         }
     }
 
-    pub fn set_watch_path<P>(&mut self, new_path: P) -> Result<(), FsmError>
-    where
-        P: AsRef<Path>,
-    {
-        if !new_path.as_ref().exists() {
+    pub fn add_watch_root(&mut self, watch_root: WatchRoot) -> Result<(), FsmError> {
+        if !watch_root.get_path().exists() {
             return Err(FsmError::new(
                 ErrorType::ConfigError,
                 format!(
                     "Invalid path {} provided.",
-                    new_path.as_ref().to_str().unwrap_or_default()
+                    watch_root.get_path().to_str().unwrap_or_default()
                 ),
             ));
         }
-        self.base_path_to_watch.clear();
-        self.base_path_to_watch.push(new_path);
+        self.watch_roots.push(watch_root);
         Ok(())
     }
 
@@ -61,14 +202,126 @@ impl ConfigManager {
         Ok(())
     }
 
-    pub fn get_watch_path(&self) -> &PathBuf {
-        &self.base_path_to_watch
+    pub fn set_stability_window(&mut self, stability_window: Duration) {
+        self.stability_window = stability_window;
+    }
+
+    pub fn set_temporary_suffixes(&mut self, temporary_suffixes: Vec<String>) {
+        self.temporary_suffixes = temporary_suffixes;
+    }
+
+    pub fn set_watch_events(&mut self, watch_events: Vec<WatchEventKind>) {
+        self.watch_events = watch_events;
+    }
+
+    pub fn set_exclude(&mut self, exclude: ExcludeSet) {
+        self.exclude = exclude;
+    }
+    /**
+    Sets every distinct schedule used by the watch roots and filters.
+    */
+    pub fn set_schedules(&mut self, schedules: Vec<SweepSchedule>) {
+        self.schedules = schedules;
+    }
+    pub fn set_move_workers(&mut self, move_workers: usize) {
+        self.move_workers = move_workers.max(1);
+    }
+    pub fn set_move_queue_size(&mut self, move_queue_size: usize) {
+        self.move_queue_size = move_queue_size.max(1);
+    }
+    pub fn get_watch_roots(&self) -> &Vec<WatchRoot> {
+        &self.watch_roots
+    }
+
+    /**
+    Returns the innermost watch root containing the given path.
+    */
+    pub fn get_watch_root<P>(&self, path: P) -> Option<&WatchRoot>
+    where
+        P: AsRef<Path>,
+    {
+        find_watch_root(&self.watch_roots, path.as_ref())
     }
 
     pub fn get_manage_path(&self) -> &PathBuf {
         &self.base_path_to_manage
     }
-    pub fn perform_overwrite_on_move(&self) -> bool {
-        self.overwrite_on_move
+    pub fn get_conflicts_path(&self) -> PathBuf {
+        self.base_path_to_manage.join("conflicts")
+    }
+    pub fn get_duplicates_path(&self) -> PathBuf {
+        self.base_path_to_manage.join("duplicates")
+    }
+    pub fn get_hash_index_path(&self) -> PathBuf {
+        self.base_path_to_manage.join("fsm_hash_index.json")
+    }
+    pub fn get_collision_strategy(&self) -> CollisionStrategy {
+        self.collision_strategy
+    }
+    pub fn get_duplicate_strategy(&self) -> DuplicateStrategy {
+        self.duplicate_strategy
+    }
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+    pub fn get_stability_window(&self) -> Duration {
+        self.stability_window
+    }
+    pub fn get_temporary_suffixes(&self) -> &Vec<String> {
+        &self.temporary_suffixes
+    }
+    pub fn get_watch_events(&self) -> &Vec<WatchEventKind> {
+        &self.watch_events
+    }
+    pub fn get_exclude(&self) -> &ExcludeSet {
+        &self.exclude
+    }
+    pub fn get_schedules(&self) -> &Vec<SweepSchedule> {
+        &self.schedules
+    }
+    pub fn get_move_workers(&self) -> usize {
+        self.move_workers
+    }
+    pub fn get_move_queue_size(&self) -> usize {
+        self.move_queue_size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn watch_root(recursive: bool, max_depth: Option<usize>) -> WatchRoot {
+        let mut watch_root = WatchRoot::new("/watched", recursive, "/managed");
+        watch_root.set_max_depth(max_depth);
+        watch_root
+    }
+
+    #[test]
+    fn limits_depth() {
+        let top_level = Path::new("/watched/a.txt");
+        let nested = Path::new("/watched/a/b/c.txt");
+
+        let flat = watch_root(false, None);
+        assert_eq!(flat.get_max_depth(), Some(0));
+        assert!(flat.is_within_depth(top_level));
+        assert!(!flat.is_within_depth(nested));
+
+        let unlimited = watch_root(true, None);
+        assert!(unlimited.is_within_depth(nested));
+        assert_eq!(unlimited.get_depth(nested), Some(2));
+
+        let limited = watch_root(true, Some(1));
+        assert!(limited.is_within_depth(Path::new("/watched/a/b.txt")));
+        assert!(!limited.is_within_depth(nested));
+        assert!(!limited.is_within_depth(Path::new("/elsewhere/a.txt")));
+    }
+
+    #[test]
+    fn only_descends_into_directories() {
+        let mut moved = watch_root(true, None);
+        moved.set_directory_handling(DirectoryHandling::Move);
+        assert_eq!(moved.get_max_depth(), Some(0));
+        assert!(!moved.is_within_depth(Path::new("/watched/a/b.txt")));
     }
 }
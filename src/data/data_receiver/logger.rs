@@ -1,17 +1,31 @@
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use log::debug;
 use log::error;
 use log::info;
 use log::warn;
 
 use crate::data::LogType;
+use crate::data::MoveStatistics;
 
 use super::DataReceiver;
 use super::Message;
 
-pub struct Logger {}
+/**
+Logs the messages on the bus, keeping count of the move outcomes.
+*/
+#[derive(Default)]
+pub struct Logger {
+    statistics: Arc<MoveStatistics>,
+}
 
 impl Logger {
     pub fn new() -> Self {
-        Self {}
+        Self::default()
+    }
+    pub fn get_statistics(&self) -> Arc<MoveStatistics> {
+        self.statistics.clone()
     }
 }
 
@@ -26,12 +40,76 @@ impl DataReceiver for Logger {
                 LogType::Warning => warn!("{:#?}", message),
                 LogType::Error => error!("{:#?}", message),
             },
-            Message::FileMove { file_to_move } => {
-                info!(
+            Message::FileMove {
+                file_to_move,
+                schedule,
+            } => match schedule {
+                Some(schedule) => debug!(
+                    "Attempting to move file {:#?} to mapped location (scheduled sweep {}).",
+                    file_to_move, schedule
+                ),
+                None => debug!(
                     "Attempting to move file {:#?} to mapped location.",
                     file_to_move
-                );
+                ),
+            },
+            Message::FileMoved {
+                from,
+                to,
+                filter,
+                action,
+            } => {
+                self.statistics.moved.fetch_add(1, Ordering::Relaxed);
+                info!("{:?} -> {:?} ({}, filter {}).", from, to, action, filter);
+            }
+            Message::FileSkipped { file, reason } => {
+                self.statistics.skipped.fetch_add(1, Ordering::Relaxed);
+                debug!("Skipped {:?}, {}.", file, reason);
+            }
+            Message::FileMoveFailed { file, error } => {
+                self.statistics.failed.fetch_add(1, Ordering::Relaxed);
+                error!("Failed to move {:?}: {}", file, error);
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::FileAction;
+    use std::path::PathBuf;
+
+    #[test]
+    fn counts_move_outcomes() {
+        let logger = Logger::new();
+        let statistics = logger.get_statistics();
+        logger.process_message(Message::FileMove {
+            file_to_move: PathBuf::from("/tmp/a.txt"),
+            schedule: None,
+        });
+        logger.process_message(Message::FileMoved {
+            from: PathBuf::from("/tmp/a.txt"),
+            to: PathBuf::from("/tmp/managed/a.txt"),
+            filter: 0,
+            action: FileAction::Move,
+        });
+        for file in ["/tmp/b.png", "/tmp/c.png"] {
+            logger.process_message(Message::FileSkipped {
+                file: PathBuf::from(file),
+                reason: "it does not match any filters".to_string(),
+            });
+        }
+        logger.process_message(Message::FileMoveFailed {
+            file: PathBuf::from("/tmp/d.txt"),
+            error: "Permission denied".to_string(),
+        });
+        logger.process_message(Message::Log {
+            message: "Swept.".to_string(),
+            message_type: LogType::Info,
+        });
+        assert_eq!(statistics.get_moved(), 1);
+        assert_eq!(statistics.get_skipped(), 2);
+        assert_eq!(statistics.get_failed(), 1);
+    }
+}
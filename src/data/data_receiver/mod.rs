@@ -1,8 +1,14 @@
 use super::Message;
 
 pub mod logger;
+pub mod pool;
 pub mod writer;
 
 pub trait DataReceiver {
     fn process_message(&self, message: Message);
+    /**
+    Waits for any work already handed to the receiver to finish. Messages received afterwards are
+    ignored.
+    */
+    fn shutdown(&mut self) {}
 }
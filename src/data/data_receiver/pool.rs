@@ -0,0 +1,312 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::hash::{Hash, Hasher};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender, SyncSender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+enum Scheduled<T> {
+    Job(Instant, usize, T),
+    Stop,
+}
+
+/**
+Hands jobs to the worker pool once their deadline has passed, from a thread of its own, so that the
+workers never wait on a full queue.
+*/
+pub struct Scheduler<T> {
+    sender: Sender<Scheduled<T>>,
+    worker_count: usize,
+    // number of jobs scheduled but not yet finished
+    pending: Arc<(Mutex<usize>, Condvar)>,
+}
+
+// derived Clone would require T: Clone
+impl<T> Clone for Scheduler<T> {
+    fn clone(&self) -> Self {
+        Self {
+            sender: self.sender.clone(),
+            worker_count: self.worker_count,
+            pending: self.pending.clone(),
+        }
+    }
+}
+
+fn get_worker(key: &impl Hash, worker_count: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    (hasher.finish() % worker_count as u64) as usize
+}
+
+impl<T> Scheduler<T> {
+    /**
+    Submits the job to the worker for `key` once `deadline` has passed. Gives the job back if the pool
+    is shutting down.
+    */
+    pub fn schedule(&self, deadline: Instant, key: &impl Hash, job: T) -> Result<(), T> {
+        if let Ok(mut count) = self.pending.0.lock() {
+            *count += 1;
+        }
+        self.send(deadline, key, job)
+    }
+
+    // the job must already be counted as pending
+    fn send(&self, deadline: Instant, key: &impl Hash, job: T) -> Result<(), T> {
+        let worker = get_worker(key, self.worker_count);
+        self.sender
+            .send(Scheduled::Job(deadline, worker, job))
+            .map_err(|e| {
+                self.finish();
+                match e.0 {
+                    Scheduled::Job(_, _, job) => job,
+                    Scheduled::Stop => unreachable!(),
+                }
+            })
+    }
+
+    fn finish(&self) {
+        let (count, finished) = &*self.pending;
+        if let Ok(mut count) = count.lock() {
+            *count = count.saturating_sub(1);
+            finished.notify_all();
+        }
+    }
+}
+
+fn run_scheduler<T>(receiver: mpsc::Receiver<Scheduled<T>>, senders: Vec<SyncSender<T>>) {
+    // keyed by deadline and arrival, so that jobs with the same deadline keep their order
+    let mut pending: BTreeMap<(Instant, u64), (usize, T)> = BTreeMap::new();
+    let mut arrival: u64 = 0;
+    loop {
+        let next_deadline = pending.keys().next().map(|(deadline, _)| *deadline);
+        let received = match next_deadline {
+            Some(deadline) => {
+                receiver.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            }
+            None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match received {
+            Ok(Scheduled::Job(deadline, worker, job)) => {
+                pending.insert((deadline, arrival), (worker, job));
+                arrival += 1;
+            }
+            // jobs still waiting for their deadline are dropped
+            Ok(Scheduled::Stop) | Err(RecvTimeoutError::Disconnected) => return,
+            Err(RecvTimeoutError::Timeout) => (),
+        }
+        let now = Instant::now();
+        while let Some(entry) = pending.first_entry() {
+            if entry.key().0 > now {
+                break;
+            }
+            let (worker, job) = entry.remove();
+            // blocks while the worker's queue is full
+            let _ = senders[worker].send(job);
+        }
+    }
+}
+
+/**
+Fixed set of worker threads, each with its own bounded queue. Jobs submitted with the same key always
+go to the same worker, so they are processed one at a time in the order they were submitted.
+Submitting blocks while `queue_size` jobs are waiting or running. Jobs can also be scheduled for
+later, including by the workers themselves, which never blocks.
+*/
+pub struct WorkerPool<T> {
+    handles: Vec<JoinHandle<()>>,
+    scheduler: Scheduler<T>,
+    scheduler_handle: JoinHandle<()>,
+    queue_size: usize,
+}
+
+impl<T> WorkerPool<T>
+where
+    T: Send + 'static,
+{
+    pub fn new<F>(worker_count: usize, queue_size: usize, handler: F) -> Self
+    where
+        F: Fn(T, &Scheduler<T>) + Send + Sync + 'static,
+    {
+        let worker_count = worker_count.max(1);
+        let queue_size = queue_size.max(1);
+        let handler = Arc::new(handler);
+        let (scheduler_tx, scheduler_rx) = mpsc::channel::<Scheduled<T>>();
+        let scheduler = Scheduler {
+            sender: scheduler_tx,
+            worker_count,
+            pending: Arc::new((Mutex::new(0), Condvar::new())),
+        };
+        let mut senders = vec![];
+        let mut handles = vec![];
+        for _ in 0..worker_count {
+            let (tx, rx) = mpsc::sync_channel::<T>((queue_size / worker_count).max(1));
+            let handler = handler.clone();
+            let scheduler = scheduler.clone();
+            // the worker exits once its senders are dropped and the queue is drained
+            handles.push(thread::spawn(move || {
+                for job in rx {
+                    // keep the worker alive if a job panics
+                    let _ = panic::catch_unwind(AssertUnwindSafe(|| handler(job, &scheduler)));
+                    scheduler.finish();
+                }
+            }));
+            senders.push(tx);
+        }
+        // the workers exit once the scheduler stops and drops their senders
+        let scheduler_handle = thread::spawn(move || run_scheduler(scheduler_rx, senders));
+        Self {
+            handles,
+            scheduler,
+            scheduler_handle,
+            queue_size,
+        }
+    }
+
+    /**
+    Submits the job to the worker for `key`, first waiting while the pool is full.
+    */
+    pub fn submit(&self, key: &impl Hash, job: T) {
+        let (count, finished) = &*self.scheduler.pending;
+        if let Ok(count) = count.lock() {
+            if let Ok(mut count) = finished.wait_while(count, |count| *count >= self.queue_size) {
+                *count += 1;
+            }
+        }
+        // the scheduler only stops once the pool is shut down
+        let _ = self.scheduler.send(Instant::now(), key, job);
+    }
+
+    /**
+    Stops accepting jobs and waits for the submitted and in-flight ones to finish. Jobs scheduled for
+    later are dropped.
+    */
+    pub fn shutdown(self) {
+        let _ = self.scheduler.sender.send(Scheduled::Stop);
+        let _ = self.scheduler_handle.join();
+        for handle in self.handles {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[test]
+    fn keeps_the_order_of_jobs_with_the_same_key() {
+        let processed = Arc::new(Mutex::new(vec![]));
+        let handled = processed.clone();
+        let pool = WorkerPool::new(4, 64, move |job: (usize, usize), _: &Scheduler<_>| {
+            // later jobs would overtake earlier ones if they ran in parallel
+            thread::sleep(Duration::from_millis((10 - job.1 as u64) % 3));
+            handled.lock().unwrap().push(job);
+        });
+        for index in 0..10 {
+            for key in 0..3 {
+                pool.submit(&key, (key, index));
+            }
+        }
+        pool.shutdown();
+        let processed = processed.lock().unwrap();
+        assert_eq!(processed.len(), 30);
+        for key in 0..3 {
+            let order: Vec<usize> = processed
+                .iter()
+                .filter(|(job_key, _)| *job_key == key)
+                .map(|(_, index)| *index)
+                .collect();
+            assert_eq!(order, (0..10).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn drains_submitted_jobs_on_shutdown() {
+        let processed = Arc::new(AtomicUsize::new(0));
+        let handled = processed.clone();
+        let pool = WorkerPool::new(2, 8, move |_: usize, _: &Scheduler<_>| {
+            thread::sleep(Duration::from_millis(5));
+            handled.fetch_add(1, Ordering::SeqCst);
+        });
+        for job in 0..20 {
+            pool.submit(&job, job);
+        }
+        pool.shutdown();
+        assert_eq!(processed.load(Ordering::SeqCst), 20);
+    }
+
+    #[test]
+    fn runs_delayed_jobs_after_their_deadline() {
+        let processed = Arc::new(Mutex::new(vec![]));
+        let handled = processed.clone();
+        let started = Instant::now();
+        let pool = WorkerPool::new(1, 8, move |retries: usize, scheduler: &Scheduler<_>| {
+            handled.lock().unwrap().push((retries, Instant::now()));
+            if retries < 2 {
+                let deadline = Instant::now() + Duration::from_millis(50);
+                assert!(scheduler.schedule(deadline, &0, retries + 1).is_ok());
+            }
+        });
+        pool.submit(&0, 0);
+        thread::sleep(Duration::from_millis(300));
+        pool.shutdown();
+        let processed = processed.lock().unwrap();
+        let retries: Vec<usize> = processed.iter().map(|(retries, _)| *retries).collect();
+        assert_eq!(retries, vec![0, 1, 2]);
+        assert!(processed[2].1 - started >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn drops_delayed_jobs_on_shutdown() {
+        let processed = Arc::new(AtomicUsize::new(0));
+        let handled = processed.clone();
+        let pool = WorkerPool::new(1, 8, move |_: usize, _: &Scheduler<_>| {
+            handled.fetch_add(1, Ordering::SeqCst);
+        });
+        let deadline = Instant::now() + Duration::from_secs(60);
+        assert!(pool.scheduler.schedule(deadline, &0, 0).is_ok());
+        pool.shutdown();
+        assert_eq!(processed.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn blocks_submitting_while_full() {
+        let running = Arc::new(AtomicUsize::new(0));
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let release_rx = Mutex::new(release_rx);
+        let handled = running.clone();
+        let pool = Arc::new(WorkerPool::new(1, 2, move |_: usize, _: &Scheduler<_>| {
+            handled.fetch_add(1, Ordering::SeqCst);
+            let _ = release_rx.lock().unwrap().recv();
+        }));
+        pool.submit(&0, 0);
+        pool.submit(&0, 1);
+
+        let submitted = Arc::new(AtomicUsize::new(0));
+        let submitter = {
+            let pool = pool.clone();
+            let submitted = submitted.clone();
+            thread::spawn(move || {
+                pool.submit(&0, 2);
+                submitted.store(1, Ordering::SeqCst);
+            })
+        };
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(submitted.load(Ordering::SeqCst), 0);
+
+        release_tx.send(()).unwrap();
+        submitter.join().unwrap();
+        assert_eq!(submitted.load(Ordering::SeqCst), 1);
+        release_tx.send(()).unwrap();
+        release_tx.send(()).unwrap();
+        match Arc::try_unwrap(pool) {
+            Ok(pool) => pool.shutdown(),
+            Err(_) => panic!("pool is still shared"),
+        }
+        assert_eq!(running.load(Ordering::SeqCst), 3);
+    }
+}
@@ -1,101 +1,1141 @@
+use std::collections::HashMap;
 use std::fs;
 use std::path::Path;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::sync::Mutex;
-use std::thread;
+use std::time::{Duration, Instant};
 
+use chrono::Local;
+use log::{info, warn};
+
+use crate::config::{ConfigManager, WatchRoot, DEFAULT_MOVE_QUEUE_SIZE, DEFAULT_MOVE_WORKERS};
+use crate::data::data_source::Publisher;
+use crate::data::RecentWrites;
 use crate::error::ErrorType;
 use crate::error::FsmError;
 use crate::filepath::FilepathManager;
+use crate::filter::CollisionStrategy;
+use crate::filter::DuplicateStrategy;
+use crate::filter::FileAction;
 use crate::filter::FilterManager;
+use crate::filter::MappedLocation;
+use crate::hash::find_duplicate;
+use crate::hash::hash_file;
+use crate::hash::HashIndex;
+use crate::journal::MoveJournal;
+use crate::schedule::SweepSchedule;
+use crate::stability::check_stability;
+use crate::stability::has_temporary_suffix;
+use crate::stability::Stability;
+use crate::stability::StabilityCheck;
+use crate::transfer::rename_file;
 
+use super::pool::Scheduler;
+use super::pool::WorkerPool;
 use super::DataReceiver;
 use super::Message;
 
-pub struct PathWriter {
-    overwrite_on_move: bool,
+/**
+Shared state needed to move a file.
+*/
+#[derive(Clone)]
+struct MoveContext {
+    config_manager: Arc<Mutex<ConfigManager>>,
     filepath_manager: Arc<Mutex<FilepathManager>>,
     filter_manager: Arc<Mutex<FilterManager>>,
+    journal: Arc<Mutex<MoveJournal>>,
+    hash_index: Arc<Mutex<HashIndex>>,
+    recent_writes: Arc<Mutex<RecentWrites>>,
+    planned_moves: Arc<Mutex<PlannedMoves>>,
+    // the outcome of every move is published back onto the message bus
+    outcomes: Publisher,
+}
+
+/**
+Destinations of the moves planned during a dry run, along with the file that would end up there, so
+that later planned moves see them as they would see existing files. Each file has at most one plan.
+*/
+type PlannedMoves = HashMap<PathBuf, PathBuf>;
+
+struct MoveJob {
+    file_to_move: PathBuf,
+    schedule: Option<SweepSchedule>,
+    // set once the file was found to be still changing
+    stability: Option<StabilityCheck>,
+}
+
+enum MoveOutcome {
+    Done(Message),
+    // the file is still being written to, and is checked again after the delay
+    Unsettled(MoveJob, Duration),
+}
+
+pub struct PathWriter {
+    context: MoveContext,
+    pool: Option<WorkerPool<MoveJob>>,
+}
+
+fn with_suffix(location: &Path, suffix: &str) -> PathBuf {
+    let mut new_filename = location.file_stem().unwrap_or_default().to_owned();
+    new_filename.push("_");
+    new_filename.push(suffix);
+    if let Some(extension) = location.extension() {
+        new_filename.push(".");
+        new_filename.push(extension);
+    }
+    location.with_file_name(new_filename)
+}
+
+/**
+Returns the file occupying the location, either on disk or, during a dry run, the file an earlier
+planned move would put there.
+*/
+fn get_occupant(location: &Path, planned: Option<&PlannedMoves>) -> Option<PathBuf> {
+    match planned.and_then(|planned| planned.get(location)) {
+        Some(res) => Some(res.clone()),
+        None => location.exists().then(|| location.to_path_buf()),
+    }
+}
+
+/**
+Appends the first free numeric suffix to the file name, always starting from the original name so
+that suffixes never compound.
+*/
+fn get_numbered_location(location: &Path, planned: Option<&PlannedMoves>) -> PathBuf {
+    let mut increment_id: u64 = 0;
+    let mut new_location = location.to_path_buf();
+    while get_occupant(&new_location, planned).is_some() {
+        new_location = with_suffix(location, &increment_id.to_string());
+        increment_id += 1;
+    }
+    new_location
+}
+
+fn get_available_location(
+    location: &Path,
+    suffix: &str,
+    planned: Option<&PlannedMoves>,
+) -> PathBuf {
+    let new_location = with_suffix(location, suffix);
+    if get_occupant(&new_location, planned).is_some() {
+        get_numbered_location(&new_location, planned)
+    } else {
+        new_location
+    }
+}
+
+/**
+Resolves where a file should be written given its mapped location. Returns `None` when the file
+should be skipped.
+*/
+fn resolve_collision(
+    file_to_move: &Path,
+    new_location: &Path,
+    collision_strategy: CollisionStrategy,
+    conflicts_path: &Path,
+    planned: Option<&PlannedMoves>,
+) -> Result<Option<PathBuf>, FsmError> {
+    let occupant = match get_occupant(new_location, planned) {
+        Some(res) => res,
+        None => return Ok(Some(new_location.to_path_buf())),
+    };
+    let resolved = match collision_strategy {
+        CollisionStrategy::Rename => Some(get_numbered_location(new_location, planned)),
+        CollisionStrategy::Skip => None,
+        CollisionStrategy::Overwrite => Some(new_location.to_path_buf()),
+        CollisionStrategy::RenameTimestamp => Some(get_available_location(
+            new_location,
+            &Local::now().format("%Y%m%d-%H%M%S").to_string(),
+            planned,
+        )),
+        CollisionStrategy::RenameHash => {
+            let hash = hash_file(file_to_move)?;
+            Some(get_available_location(new_location, &hash[..8], planned))
+        }
+        CollisionStrategy::KeepNewer => {
+            let incoming = fs::metadata(file_to_move)?.modified()?;
+            let existing = fs::metadata(occupant)?.modified()?;
+            (incoming > existing).then(|| new_location.to_path_buf())
+        }
+        CollisionStrategy::KeepLarger => {
+            let incoming = fs::metadata(file_to_move)?.len();
+            let existing = fs::metadata(occupant)?.len();
+            (incoming > existing).then(|| new_location.to_path_buf())
+        }
+        CollisionStrategy::Conflicts => Some(get_numbered_location(
+            &conflicts_path.join(new_location.file_name().unwrap_or_default()),
+            planned,
+        )),
+    };
+    Ok(resolved)
+}
+
+#[cfg(unix)]
+fn create_symlink(original: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(original, link)
+}
+
+#[cfg(windows)]
+fn create_symlink(original: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::windows::fs::symlink_file(original, link)
 }
 
+/**
+Moves the file with the given action. During a dry run (when `planned` is set) the move is only
+reported and added to the planned moves.
+*/
 fn move_file(
     file_to_move: impl AsRef<Path>,
     new_location: impl AsRef<Path>,
-    overwrite_on_move: bool,
-) -> Result<(), FsmError> {
+    action: FileAction,
+    collision_strategy: CollisionStrategy,
+    conflicts_path: &Path,
+    planned: Option<&mut PlannedMoves>,
+) -> Result<Option<PathBuf>, FsmError> {
     let old_location = file_to_move.as_ref().to_path_buf();
-    let mut new_location = new_location.as_ref().to_path_buf();
-    if overwrite_on_move || !new_location.exists() {
-        // ideal to add a mechanism for logging
-        match fs::rename(&old_location, &new_location) {
-            Ok(_) => Ok(()),
-            Err(e) => Err(FsmError::new(ErrorType::FilterError, e.to_string())),
+    let new_path = match resolve_collision(
+        &old_location,
+        new_location.as_ref(),
+        collision_strategy,
+        conflicts_path,
+        planned.as_deref(),
+    )? {
+        Some(res) => res,
+        // skipped, reported by the caller
+        None => return Ok(None),
+    };
+    if let Some(planned) = planned {
+        info!(
+            "[dry run] Would {} {:?} to {:?}.",
+            action, old_location, new_path
+        );
+        planned.insert(new_path.clone(), old_location);
+        return Ok(Some(new_path));
+    }
+    // templated destinations are created on demand
+    if let Some(parent) = new_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // links cannot replace an existing file, so clear the way when overwriting
+    if matches!(action, FileAction::Hardlink | FileAction::Symlink)
+        && fs::symlink_metadata(&new_path).is_ok()
+    {
+        fs::remove_file(&new_path)?;
+    }
+    // ideal to add a mechanism for logging
+    let result = match action {
+        FileAction::Move => rename_file(&old_location, &new_path),
+        FileAction::Copy => fs::copy(&old_location, &new_path).map(|_| ()),
+        FileAction::Hardlink => fs::hard_link(&old_location, &new_path),
+        FileAction::Symlink => create_symlink(
+            &fs::canonicalize(&old_location).unwrap_or(old_location.clone()),
+            &new_path,
+        ),
+        FileAction::MoveAndLeaveSymlink => rename_file(&old_location, &new_path).and_then(|_| {
+            match is_left_behind(&old_location) {
+                true => Ok(()),
+                false => create_symlink(&new_path, &old_location),
+            }
+        }),
+    };
+    match result {
+        Ok(_) => Ok(Some(new_path)),
+        Err(e) => Err(FsmError::new(ErrorType::FilterError, e.to_string())),
+    }
+}
+
+/**
+Whether a moved file is still in place as a regular file, which happens when a move across devices
+could not remove the original after copying it.
+*/
+fn is_left_behind(file: &Path) -> bool {
+    fs::symlink_metadata(file).is_ok_and(|metadata| !metadata.file_type().is_symlink())
+}
+
+/**
+Action to record for a file handled with the given action. A move that left the original behind is
+recorded as a copy, so that the original is not picked up again and undo only removes the copy.
+*/
+fn get_performed_action(file: &Path, action: FileAction) -> FileAction {
+    match action {
+        FileAction::Move | FileAction::MoveAndLeaveSymlink if is_left_behind(file) => {
+            FileAction::Copy
+        }
+        _ => action,
+    }
+}
+
+/**
+Handles a file whose contents match `duplicate_of`. Returns the new location of the file if it was
+moved to the duplicates folder, or None if it is left in place.
+*/
+fn handle_duplicate(
+    file_to_move: &Path,
+    duplicate_of: &Path,
+    duplicate_strategy: DuplicateStrategy,
+    duplicates_path: &Path,
+    planned: Option<&mut PlannedMoves>,
+) -> Result<Option<PathBuf>, FsmError> {
+    match duplicate_strategy {
+        DuplicateStrategy::Keep | DuplicateStrategy::Skip => Ok(None),
+        DuplicateStrategy::Duplicates => {
+            info!("{:?} is a duplicate of {:?}.", file_to_move, duplicate_of);
+            move_file(
+                file_to_move,
+                duplicates_path.join(file_to_move.file_name().unwrap_or_default()),
+                FileAction::Move,
+                CollisionStrategy::Rename,
+                duplicates_path,
+                planned,
+            )
+        }
+    }
+}
+
+/**
+Where a file goes and how, copied out of the matching filter so that the filters are not kept locked
+while the file is moved.
+*/
+struct MoveTarget {
+    location: PathBuf,
+    filter_index: usize,
+    directory_key: String,
+    action: FileAction,
+    collision_strategy: Option<CollisionStrategy>,
+    duplicate_strategy: Option<DuplicateStrategy>,
+}
+
+fn get_move_target(
+    filter_manager: &Mutex<FilterManager>,
+    file_to_move: &Path,
+    watch_root: Option<&WatchRoot>,
+    schedule: Option<&SweepSchedule>,
+    filepath_manager: Arc<Mutex<FilepathManager>>,
+) -> Result<MoveTarget, FsmError> {
+    let filter_manager = match filter_manager.lock() {
+        Err(e) => return Err(FsmError::new(ErrorType::FilterError, e.to_string())),
+        Ok(res) => res,
+    };
+    let MappedLocation {
+        location,
+        filter_index,
+        filter,
+    } = match watch_root {
+        Some(watch_root) => filter_manager.get_mapping_in_root(
+            &file_to_move,
+            watch_root,
+            schedule,
+            filepath_manager,
+        )?,
+        None => filter_manager.get_mapping(&file_to_move, schedule, filepath_manager)?,
+    };
+    Ok(MoveTarget {
+        location,
+        filter_index,
+        directory_key: filter.get_directory_key().to_string(),
+        action: filter.get_action(),
+        collision_strategy: filter.get_collision_strategy(),
+        duplicate_strategy: filter.get_duplicate_strategy(),
+    })
+}
+
+fn skipped(file: PathBuf, reason: impl Into<String>) -> Message {
+    Message::FileSkipped {
+        file,
+        reason: reason.into(),
+    }
+}
+
+/**
+Moves a file once it is no longer being written to. Files that are still changing are handed back to
+be checked again later, instead of holding up the worker.
+*/
+fn process_file_move(job: MoveJob, context: MoveContext) -> Result<MoveOutcome, FsmError> {
+    let (stability_window, temporary_suffixes) = match context.config_manager.lock() {
+        Err(e) => return Err(FsmError::new(ErrorType::ConfigError, e.to_string())),
+        Ok(res) => (
+            res.get_stability_window(),
+            res.get_temporary_suffixes().clone(),
+        ),
+    };
+    let MoveJob {
+        file_to_move,
+        schedule,
+        stability,
+    } = job;
+    // partial downloads are only picked up once they are renamed to their final name
+    if has_temporary_suffix(&file_to_move, &temporary_suffixes) {
+        return Ok(MoveOutcome::Done(skipped(
+            file_to_move,
+            "it is a temporary file",
+        )));
+    }
+    let skip_reason = match check_stability(&file_to_move, stability_window, stability) {
+        Stability::Stable => {
+            return move_settled_file(file_to_move, schedule, context).map(MoveOutcome::Done)
+        }
+        Stability::Changing(stability) => {
+            let job = MoveJob {
+                file_to_move,
+                schedule,
+                stability: Some(stability),
+            };
+            return Ok(MoveOutcome::Unsettled(job, stability_window));
+        }
+        Stability::Missing => "it no longer exists",
+        Stability::GaveUp => "it is still being written to",
+    };
+    Ok(MoveOutcome::Done(skipped(file_to_move, skip_reason)))
+}
+
+fn move_settled_file(
+    file_to_move: PathBuf,
+    schedule: Option<SweepSchedule>,
+    context: MoveContext,
+) -> Result<Message, FsmError> {
+    let MoveContext {
+        config_manager,
+        filepath_manager,
+        filter_manager,
+        journal,
+        hash_index,
+        recent_writes,
+        planned_moves,
+        ..
+    } = context;
+    let config_manager = match config_manager.lock() {
+        Err(e) => return Err(FsmError::new(ErrorType::ConfigError, e.to_string())),
+        Ok(res) => res,
+    };
+    let default_collision_strategy = config_manager.get_collision_strategy();
+    let default_duplicate_strategy = config_manager.get_duplicate_strategy();
+    let dry_run = config_manager.is_dry_run();
+    let conflicts_path = config_manager.get_conflicts_path();
+    let duplicates_path = config_manager.get_duplicates_path();
+    let watch_root = config_manager.get_watch_root(&file_to_move).cloned();
+    let manage_path = config_manager.get_manage_path().clone();
+    drop(config_manager);
+
+    // files that were mirrored into the managed tree are left in place for good, and files put
+    // back by an undo (possibly from another fsm process) stay where they are
+    let (is_mirrored, is_restored) = match journal.lock() {
+        Err(e) => return Err(FsmError::new(ErrorType::JournalError, e.to_string())),
+        Ok(mut res) => {
+            res.refresh()?;
+            (
+                res.is_mirrored(&file_to_move),
+                res.is_restored(&file_to_move),
+            )
+        }
+    };
+    if is_mirrored {
+        return Ok(skipped(
+            file_to_move,
+            "it was already mirrored into the managed directory",
+        ));
+    }
+    if is_restored {
+        return Ok(skipped(file_to_move, "it was put back by an undo"));
+    }
+    let mapping = match get_move_target(
+        &filter_manager,
+        &file_to_move,
+        watch_root.as_ref(),
+        schedule.as_ref(),
+        filepath_manager.clone(),
+    ) {
+        Err(e) => {
+            if dry_run {
+                info!(
+                    "[dry run] Would not move {:?}: {}",
+                    file_to_move,
+                    e.get_error_message()
+                );
+            }
+            return match e.get_error_type() {
+                ErrorType::NoMatchError => {
+                    Ok(skipped(file_to_move, "it does not match any filters"))
+                }
+                ErrorType::OffScheduleError => Ok(skipped(
+                    file_to_move,
+                    "its filter only applies during its scheduled sweeps",
+                )),
+                _ => Err(e),
+            };
         }
+        Ok(res) => res,
+    };
+    let action = mapping.action;
+    let directory_key = mapping.directory_key.as_str();
+    let collision_strategy = mapping
+        .collision_strategy
+        .unwrap_or(default_collision_strategy);
+    let duplicate_strategy = mapping
+        .duplicate_strategy
+        .unwrap_or(default_duplicate_strategy);
+    let managed_root = watch_root
+        .as_ref()
+        .map_or(&manage_path, |watch_root| watch_root.get_managed_path());
+    // the same directory key lives in a different directory under each managed root
+    let index_key = if *managed_root == manage_path {
+        directory_key.to_string()
     } else {
-        let new_path = {
-            // todo - come up with a better renaming scheme
-            let mut increment_id: u64 = 0;
-            while new_location.exists() {
-                let mut new_filename = new_location
-                    .file_stem()
-                    .unwrap_or_default()
-                    .to_str()
-                    .unwrap_or_default()
-                    .to_owned();
-                new_filename.push_str(&("_".to_string() + increment_id.to_string().as_str()));
-                let file_extension = new_location.extension().unwrap_or_default().to_owned();
-                new_location.set_file_name(new_filename);
-                new_location.set_extension(file_extension);
-                increment_id += 1;
+        format!("{}@{}", directory_key, managed_root.to_string_lossy())
+    };
+
+    // held until the planned move is recorded, so that concurrent planned moves see each other
+    let mut planned_moves = match dry_run {
+        false => None,
+        true => match planned_moves.lock() {
+            Err(e) => return Err(FsmError::new(ErrorType::ApplicationError, e.to_string())),
+            Ok(mut res) => {
+                // a file found again by a later sweep replaces its own plan instead of colliding with it
+                res.retain(|_, source| *source != file_to_move);
+                Some(res)
             }
-            new_location
+        },
+    };
+    let hash = if action == FileAction::Move
+        && duplicate_strategy != DuplicateStrategy::Keep
+        && file_to_move.is_file()
+    {
+        Some(hash_file(&file_to_move)?)
+    } else {
+        None
+    };
+    if let Some(hash) = &hash {
+        let duplicate_of = if mapping.location.is_file() && hash_file(&mapping.location)? == *hash {
+            Some(mapping.location.clone())
+        } else {
+            let directory = match filepath_manager.lock() {
+                Err(e) => return Err(FsmError::new(ErrorType::FilepathError, e.to_string())),
+                Ok(res) => res
+                    .get_in_root(managed_root, directory_key)
+                    .cloned()
+                    .unwrap_or_default(),
+            };
+            find_duplicate(&hash_index, &index_key, &directory, hash, !dry_run)?
         };
-        // todo - potentially reduce some duplication here
-        match fs::rename(&old_location, &new_path) {
-            Ok(_) => Ok(()),
-            Err(e) => Err(FsmError::new(ErrorType::FilterError, e.to_string())),
+        if let Some(duplicate_of) = duplicate_of {
+            let new_location = handle_duplicate(
+                &file_to_move,
+                &duplicate_of,
+                duplicate_strategy,
+                &duplicates_path,
+                planned_moves.as_deref_mut(),
+            )?;
+            let new_location = match (new_location, dry_run) {
+                (Some(new_location), false) => new_location,
+                (Some(new_location), true) => {
+                    return Ok(skipped(
+                        file_to_move,
+                        format!(
+                            "it is a duplicate of {:?} (dry run, it would be moved to {:?})",
+                            duplicate_of, new_location
+                        ),
+                    ))
+                }
+                (None, _) => {
+                    return Ok(skipped(
+                        file_to_move,
+                        format!(
+                            "it is a duplicate of {:?}, left in place by on_duplicate",
+                            duplicate_of
+                        ),
+                    ))
+                }
+            };
+            let action = get_performed_action(&file_to_move, FileAction::Move);
+            let mut journal = match journal.lock() {
+                Err(e) => return Err(FsmError::new(ErrorType::JournalError, e.to_string())),
+                Ok(res) => res,
+            };
+            journal.record_move(
+                &file_to_move,
+                &new_location,
+                action,
+                mapping.filter_index,
+                directory_key,
+            )?;
+            return Ok(Message::FileMoved {
+                from: file_to_move,
+                to: new_location,
+                filter: mapping.filter_index,
+                action,
+            });
         }
     }
+
+    let new_location = match move_file(
+        &file_to_move,
+        &mapping.location,
+        action,
+        collision_strategy,
+        &conflicts_path,
+        planned_moves.as_deref_mut(),
+    )? {
+        Some(res) => res,
+        None => {
+            return Ok(skipped(
+                file_to_move,
+                format!("{:?} already exists", mapping.location),
+            ))
+        }
+    };
+    if dry_run {
+        return Ok(skipped(
+            file_to_move,
+            format!("dry run, it would be moved to {:?}", new_location),
+        ));
+    }
+    let action = get_performed_action(&file_to_move, action);
+    if let Ok(mut recent_writes) = recent_writes.lock() {
+        recent_writes.insert(&new_location);
+        if action != FileAction::Move {
+            recent_writes.insert(&file_to_move);
+        }
+    }
+    let mut journal = match journal.lock() {
+        Err(e) => return Err(FsmError::new(ErrorType::JournalError, e.to_string())),
+        Ok(res) => res,
+    };
+    journal.record_move(
+        &file_to_move,
+        &new_location,
+        action,
+        mapping.filter_index,
+        directory_key,
+    )?;
+    if let Some(hash) = &hash {
+        match hash_index.lock() {
+            Err(e) => return Err(FsmError::new(ErrorType::ApplicationError, e.to_string())),
+            Ok(mut res) => res.insert(&index_key, hash, &new_location)?,
+        };
+    }
+    Ok(Message::FileMoved {
+        from: file_to_move,
+        to: new_location,
+        filter: mapping.filter_index,
+        action,
+    })
+}
+
+/**
+Directory the file would be moved into, falling back to the directory it is in when it cannot be
+mapped. Moves into the same directory are made one at a time, so that they see each other's files
+when resolving collisions.
+*/
+fn get_route_key(job: &MoveJob, context: &MoveContext) -> Option<PathBuf> {
+    let watch_root = match context.config_manager.lock() {
+        Err(_) => None,
+        Ok(res) => res.get_watch_root(&job.file_to_move).cloned(),
+    };
+    match get_move_target(
+        &context.filter_manager,
+        &job.file_to_move,
+        watch_root.as_ref(),
+        job.schedule.as_ref(),
+        context.filepath_manager.clone(),
+    ) {
+        Ok(target) => target.location.parent().map(Path::to_path_buf),
+        Err(_) => job.file_to_move.parent().map(Path::to_path_buf),
+    }
 }
 
 impl PathWriter {
     pub fn new(
-        overwrite_on_move: bool,
+        config_manager: Arc<Mutex<ConfigManager>>,
         filepath_manager: Arc<Mutex<FilepathManager>>,
         filter_manager: Arc<Mutex<FilterManager>>,
+        journal: Arc<Mutex<MoveJournal>>,
+        hash_index: Arc<Mutex<HashIndex>>,
+        recent_writes: Arc<Mutex<RecentWrites>>,
+        publisher: Publisher,
     ) -> Self {
-        Self {
-            overwrite_on_move,
+        let (worker_count, queue_size) = match config_manager.lock() {
+            Err(_) => (DEFAULT_MOVE_WORKERS, DEFAULT_MOVE_QUEUE_SIZE),
+            Ok(res) => (res.get_move_workers(), res.get_move_queue_size()),
+        };
+        let context = MoveContext {
+            config_manager,
             filepath_manager,
             filter_manager,
+            journal,
+            hash_index,
+            recent_writes,
+            planned_moves: Arc::new(Mutex::new(PlannedMoves::new())),
+            outcomes: publisher,
+        };
+        let worker_context = context.clone();
+        let pool = WorkerPool::new(
+            worker_count,
+            queue_size,
+            move |job: MoveJob, scheduler: &Scheduler<MoveJob>| {
+                let context = &worker_context;
+                let file_to_move = job.file_to_move.clone();
+                let outcome = match process_file_move(job, context.clone()) {
+                    Ok(MoveOutcome::Done(res)) => res,
+                    Ok(MoveOutcome::Unsettled(job, delay)) => {
+                        let retry_key = get_route_key(&job, context);
+                        match scheduler.schedule(Instant::now() + delay, &retry_key, job) {
+                            Ok(_) => return,
+                            Err(_) => skipped(file_to_move, "fsm is shutting down"),
+                        }
+                    }
+                    Err(e) => Message::FileMoveFailed {
+                        file: file_to_move,
+                        error: e.get_error_message().to_string(),
+                    },
+                };
+                context.outcomes.publish(outcome);
+            },
+        );
+        Self {
+            context,
+            pool: Some(pool),
         }
     }
 }
 
 impl DataReceiver for PathWriter {
     fn process_message(&self, message: Message) {
-        match message {
-            Message::FileMove { file_to_move } => {
-                let write_on_move = self.overwrite_on_move;
-                // ideally have a nice way to log this without using macro directly (through logger)
-                let filepath_manager = self.filepath_manager.clone();
-                let filter_manager = self.filter_manager.clone();
-                let _handle = thread::spawn(move || {
-                    let filter_manager = match filter_manager.lock() {
-                        // need to fix error types as well
-                        Err(e) => return Err(FsmError::new(ErrorType::FilterError, e.to_string())),
-                        Ok(res) => res,
-                    };
-                    let mapped_location = match filter_manager
-                        .get_mapped_location(&file_to_move, filepath_manager)
-                    {
-                        Err(e) => return Err(FsmError::new(ErrorType::FilterError, e.to_string())),
-                        Ok(res) => res,
-                    };
-                    move_file(file_to_move.clone(), mapped_location, write_on_move)
+        if let Message::FileMove {
+            file_to_move,
+            schedule,
+        } = message
+        {
+            let pool = match &self.pool {
+                Some(res) => res,
+                None => {
+                    warn!("Ignoring {:?}, fsm is shutting down.", file_to_move);
+                    return;
+                }
+            };
+            // files bound for the same directory are moved one at a time, in the order they came in
+            let job = MoveJob {
+                file_to_move,
+                schedule,
+                stability: None,
+            };
+            let route_key = get_route_key(&job, &self.context);
+            pool.submit(&route_key, job);
+        }
+    }
+
+    fn shutdown(&mut self) {
+        if let Some(pool) = self.pool.take() {
+            pool.shutdown();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::time::{Duration, SystemTime};
+
+    fn test_directory(name: &str) -> PathBuf {
+        let directory = env::temp_dir().join(format!("fsm_writer_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(directory.join("conflicts")).unwrap();
+        directory
+    }
+
+    fn resolve(
+        incoming: &Path,
+        existing: &Path,
+        collision_strategy: CollisionStrategy,
+    ) -> Option<PathBuf> {
+        let conflicts_path = existing.parent().unwrap().join("conflicts");
+        resolve_collision(
+            incoming,
+            existing,
+            collision_strategy,
+            &conflicts_path,
+            None,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn numbers_locations_from_the_original_name() {
+        let directory = test_directory("numbered");
+        let location = directory.join("report.pdf");
+        assert_eq!(get_numbered_location(&location, None), location);
+
+        fs::write(&location, "").unwrap();
+        assert_eq!(
+            get_numbered_location(&location, None),
+            directory.join("report_0.pdf")
+        );
+        fs::write(directory.join("report_0.pdf"), "").unwrap();
+        fs::write(directory.join("report_1.pdf"), "").unwrap();
+        assert_eq!(
+            get_numbered_location(&location, None),
+            directory.join("report_2.pdf")
+        );
+
+        let no_extension = directory.join("Makefile");
+        fs::write(&no_extension, "").unwrap();
+        assert_eq!(
+            get_numbered_location(&no_extension, None),
+            directory.join("Makefile_0")
+        );
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn numbers_around_planned_moves() {
+        let directory = test_directory("planned");
+        let location = directory.join("report.pdf");
+        let mut planned = PlannedMoves::new();
+        planned.insert(location.clone(), directory.join("incoming.pdf"));
+        assert_eq!(
+            get_numbered_location(&location, Some(&planned)),
+            directory.join("report_0.pdf")
+        );
+        assert_eq!(get_numbered_location(&location, None), location);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn resolves_collisions() {
+        let directory = test_directory("collisions");
+        let existing = directory.join("report.pdf");
+        let incoming = directory.join("incoming.pdf");
+        let free = directory.join("free.pdf");
+        fs::write(&existing, "existing").unwrap();
+        fs::write(&incoming, "a larger incoming file").unwrap();
+
+        assert_eq!(
+            resolve(&incoming, &free, CollisionStrategy::Skip),
+            Some(free)
+        );
+        assert_eq!(
+            resolve(&incoming, &existing, CollisionStrategy::Rename),
+            Some(directory.join("report_0.pdf"))
+        );
+        assert_eq!(resolve(&incoming, &existing, CollisionStrategy::Skip), None);
+        assert_eq!(
+            resolve(&incoming, &existing, CollisionStrategy::Overwrite),
+            Some(existing.clone())
+        );
+        assert_eq!(
+            resolve(&incoming, &existing, CollisionStrategy::Conflicts),
+            Some(directory.join("conflicts").join("report.pdf"))
+        );
+
+        let hashed = resolve(&incoming, &existing, CollisionStrategy::RenameHash).unwrap();
+        let hash = hash_file(&incoming).unwrap();
+        assert_eq!(hashed, directory.join(format!("report_{}.pdf", &hash[..8])));
+        let timestamped =
+            resolve(&incoming, &existing, CollisionStrategy::RenameTimestamp).unwrap();
+        let name = timestamped.file_name().unwrap().to_str().unwrap();
+        assert!(
+            name.starts_with("report_") && name.ends_with(".pdf"),
+            "{}",
+            name
+        );
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn keeps_newer_and_larger_files() {
+        let directory = test_directory("keep");
+        let existing = directory.join("report.pdf");
+        let incoming = directory.join("incoming.pdf");
+        fs::write(&existing, "existing").unwrap();
+        fs::write(&incoming, "small").unwrap();
+        let set_modified = |file: &Path, modified: SystemTime| {
+            fs::File::options()
+                .write(true)
+                .open(file)
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        };
+        let now = SystemTime::now();
+        set_modified(&existing, now - Duration::from_secs(60));
+        set_modified(&incoming, now);
+
+        assert_eq!(
+            resolve(&incoming, &existing, CollisionStrategy::KeepNewer),
+            Some(existing.clone())
+        );
+        assert_eq!(
+            resolve(&incoming, &existing, CollisionStrategy::KeepLarger),
+            None
+        );
+
+        fs::write(&incoming, "a larger incoming file").unwrap();
+        set_modified(&incoming, now - Duration::from_secs(120));
+        assert_eq!(
+            resolve(&incoming, &existing, CollisionStrategy::KeepNewer),
+            None
+        );
+        assert_eq!(
+            resolve(&incoming, &existing, CollisionStrategy::KeepLarger),
+            Some(existing.clone())
+        );
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    struct Outcomes(Arc<Mutex<Vec<Message>>>);
+
+    impl DataReceiver for Outcomes {
+        fn process_message(&self, message: Message) {
+            if !matches!(message, Message::FileMove { .. }) {
+                self.0.lock().unwrap().push(message);
+            }
+        }
+    }
+
+    /**
+    Writer moving `.txt` files from anywhere into `managed/categorized/a`, along with the outcomes it publishes.
+    */
+    fn test_writer(
+        directory: &Path,
+        dry_run: bool,
+        duplicate_strategy: DuplicateStrategy,
+    ) -> (PathWriter, Arc<Mutex<Vec<Message>>>) {
+        let managed = directory.join("managed");
+        let mut config_manager = ConfigManager::new(
+            vec![],
+            &managed,
+            CollisionStrategy::Rename,
+            duplicate_strategy,
+            dry_run,
+        );
+        config_manager.set_stability_window(Duration::ZERO);
+        let structure = serde_json::from_str(r#"{"a": "a"}"#).unwrap();
+        fs::create_dir_all(&managed).unwrap();
+        let filepath_manager = FilepathManager::new(&managed, &structure, true).unwrap();
+        let filters =
+            serde_json::from_str(r#"[{"allowed_filetypes": ["txt"], "directory_key": "a"}]"#)
+                .unwrap();
+        let journal = MoveJournal::open(managed.join("fsm_journal.jsonl")).unwrap();
+        let hash_index = HashIndex::open(config_manager.get_hash_index_path()).unwrap();
+
+        let outcomes = Arc::new(Mutex::new(vec![]));
+        let mut message_manager = crate::data::MessageManager::new();
+        message_manager.add_receiver(Box::new(Outcomes(outcomes.clone())));
+        let writer = PathWriter::new(
+            Arc::new(Mutex::new(config_manager)),
+            Arc::new(Mutex::new(filepath_manager)),
+            Arc::new(Mutex::new(FilterManager::new(filters))),
+            Arc::new(Mutex::new(journal)),
+            Arc::new(Mutex::new(hash_index)),
+            Arc::new(Mutex::new(RecentWrites::new())),
+            Publisher::new(message_manager.get_receivers()),
+        );
+        (writer, outcomes)
+    }
+
+    fn file_move(file: &Path) -> Message {
+        Message::FileMove {
+            file_to_move: file.to_path_buf(),
+            schedule: None,
+        }
+    }
+
+    #[test]
+    fn publishes_the_outcome_of_each_move() {
+        let directory = test_directory("outcomes");
+        let incoming = directory.join("incoming");
+        fs::create_dir_all(&incoming).unwrap();
+        fs::write(incoming.join("notes.txt"), "notes").unwrap();
+        fs::write(incoming.join("image.png"), "image").unwrap();
+        let managed = directory.join("managed").join("categorized").join("a");
+        fs::create_dir_all(&managed).unwrap();
+        fs::write(managed.join("taken.txt"), "").unwrap();
+        fs::write(incoming.join("taken.txt"), "taken").unwrap();
+
+        let (mut writer, outcomes) = test_writer(&directory, false, DuplicateStrategy::Keep);
+        for name in ["notes.txt", "image.png", "missing.txt", "taken.txt"] {
+            writer.process_message(file_move(&incoming.join(name)));
+        }
+        writer.shutdown();
+        writer.context.outcomes.wait_until_published();
+        // messages arriving after shutdown are ignored
+        writer.process_message(file_move(&incoming.join("notes.txt")));
+
+        assert_eq!(
+            fs::read_to_string(managed.join("notes.txt")).unwrap(),
+            "notes"
+        );
+        assert_eq!(
+            fs::read_to_string(managed.join("taken_0.txt")).unwrap(),
+            "taken"
+        );
+        assert!(incoming.join("image.png").exists());
+
+        let outcomes = outcomes.lock().unwrap();
+        assert_eq!(outcomes.len(), 4, "{:?}", outcomes);
+        let find = |name: &str| {
+            outcomes
+                .iter()
+                .find(|outcome| match outcome {
+                    Message::FileMoved { from: file, .. }
+                    | Message::FileSkipped { file, .. }
+                    | Message::FileMoveFailed { file, .. } => file.ends_with(name),
+                    _ => false,
+                })
+                .unwrap()
+        };
+        assert!(matches!(
+            find("notes.txt"),
+            Message::FileMoved { to, filter: 0, action: FileAction::Move, .. }
+                if *to == managed.join("notes.txt")
+        ));
+        assert!(matches!(
+            find("image.png"),
+            Message::FileSkipped { reason, .. } if reason == "it does not match any filters"
+        ));
+        assert!(matches!(
+            find("missing.txt"),
+            Message::FileSkipped { reason, .. } if reason == "it no longer exists"
+        ));
+        assert!(matches!(
+            find("taken.txt"),
+            Message::FileMoved { to, .. } if *to == managed.join("taken_0.txt")
+        ));
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn routes_moves_by_their_destination() {
+        let directory = test_directory("route");
+        for source in ["one", "two"] {
+            fs::create_dir_all(directory.join(source)).unwrap();
+        }
+        for file in ["one/a.txt", "two/b.txt", "one/c.png"] {
+            fs::write(directory.join(file), "").unwrap();
+        }
+        let (mut writer, _) = test_writer(&directory, false, DuplicateStrategy::Keep);
+        let job = |file: PathBuf| MoveJob {
+            file_to_move: file,
+            schedule: None,
+            stability: None,
+        };
+        let managed = directory.join("managed").join("categorized").join("a");
+        assert_eq!(
+            get_route_key(&job(directory.join("one").join("a.txt")), &writer.context),
+            Some(managed.clone())
+        );
+        assert_eq!(
+            get_route_key(&job(directory.join("two").join("b.txt")), &writer.context),
+            Some(managed)
+        );
+        // files that match no filter stay with their own directory
+        assert_eq!(
+            get_route_key(&job(directory.join("one").join("c.png")), &writer.context),
+            Some(directory.join("one"))
+        );
+        writer.shutdown();
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn plans_the_same_move_on_every_dry_run() {
+        let directory = test_directory("dry_run");
+        let incoming = directory.join("incoming");
+        fs::create_dir_all(&incoming).unwrap();
+        let file = incoming.join("report.txt");
+        fs::write(&file, "report").unwrap();
+        fs::write(incoming.join("other.txt"), "other").unwrap();
+        let managed = directory.join("managed").join("categorized").join("a");
+        fs::create_dir_all(&managed).unwrap();
+        fs::write(managed.join("report.txt"), "").unwrap();
+
+        let (mut writer, _) = test_writer(&directory, true, DuplicateStrategy::Keep);
+        let mut plans = vec![];
+        for _ in 0..2 {
+            for name in ["report.txt", "other.txt"] {
+                let outcome = move_settled_file(incoming.join(name), None, writer.context.clone());
+                plans.push(match outcome.unwrap() {
+                    Message::FileSkipped { reason, .. } => reason,
+                    outcome => panic!("{:?}", outcome),
                 });
             }
-            _ => {}
         }
+        assert_eq!(
+            plans[0],
+            format!(
+                "dry run, it would be moved to {:?}",
+                managed.join("report_0.txt")
+            )
+        );
+        assert_eq!(plans[0..2], plans[2..4]);
+        assert!(file.exists() && !managed.join("report_0.txt").exists());
+        writer.shutdown();
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn leaves_duplicates_in_place() {
+        let directory = test_directory("duplicates");
+        let incoming = directory.join("incoming");
+        fs::create_dir_all(&incoming).unwrap();
+        let managed = directory.join("managed").join("categorized").join("a");
+        fs::create_dir_all(&managed).unwrap();
+        fs::write(managed.join("original.txt"), "same").unwrap();
+        let copy = incoming.join("copy.txt");
+        fs::write(&copy, "same").unwrap();
+        let index_path = directory.join("managed").join("fsm_hash_index.json");
+
+        let (mut writer, _) = test_writer(&directory, true, DuplicateStrategy::Duplicates);
+        let outcome = move_settled_file(copy.clone(), None, writer.context.clone()).unwrap();
+        assert!(matches!(
+            outcome,
+            Message::FileSkipped { reason, .. } if reason.contains("dry run, it would be moved to")
+        ));
+        assert!(!index_path.exists());
+        writer.shutdown();
+
+        let (mut writer, _) = test_writer(&directory, false, DuplicateStrategy::Skip);
+        let outcome = move_settled_file(copy.clone(), None, writer.context.clone()).unwrap();
+        assert!(matches!(
+            outcome,
+            Message::FileSkipped { reason, .. } if reason == format!(
+                "it is a duplicate of {:?}, left in place by on_duplicate",
+                managed.join("original.txt")
+            )
+        ));
+        assert!(copy.exists() && !managed.join("copy.txt").exists());
+        assert!(index_path.exists());
+        writer.shutdown();
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn publishes_failed_moves() {
+        let directory = test_directory("failed");
+        let incoming = directory.join("incoming");
+        fs::create_dir_all(&incoming).unwrap();
+        let notes = incoming.join("notes.txt");
+        fs::write(&notes, "notes").unwrap();
+
+        let (mut writer, outcomes) = test_writer(&directory, false, DuplicateStrategy::Keep);
+        // the destination directory is replaced by a file, so nothing can be moved into it
+        let managed = directory.join("managed").join("categorized").join("a");
+        fs::remove_dir_all(&managed).unwrap();
+        fs::write(&managed, "").unwrap();
+        writer.process_message(file_move(&notes));
+        writer.shutdown();
+        writer.context.outcomes.wait_until_published();
+
+        let outcomes = outcomes.lock().unwrap();
+        assert!(
+            matches!(
+                outcomes.as_slice(),
+                [Message::FileMoveFailed { file, .. }] if *file == notes
+            ),
+            "{:?}",
+            outcomes
+        );
+        assert!(notes.exists());
+        fs::remove_dir_all(&directory).unwrap();
     }
 }
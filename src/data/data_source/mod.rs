@@ -1,4 +1,9 @@
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
 use super::{Message, Watchers};
+use crate::error::FsmError;
 
 pub mod sweep;
 pub mod watch;
@@ -14,15 +19,121 @@ pub fn notify_receivers(message: Message, receivers: Watchers) {
     }
 }
 
+/**
+Publishes messages onto the bus from a thread of its own. Lets workers report back without waiting
+on the bus, which may be held by a data source that is itself waiting on those workers.
+*/
+#[derive(Clone)]
+pub struct Publisher {
+    sender: Sender<Message>,
+    // number of messages sent but not yet published
+    pending: Arc<(Mutex<usize>, Condvar)>,
+}
+
+impl Publisher {
+    pub fn new(receivers: Watchers) -> Self {
+        let (tx, rx) = mpsc::channel::<Message>();
+        let pending = Arc::new((Mutex::new(0), Condvar::new()));
+        let thread_pending = pending.clone();
+        thread::spawn(move || {
+            for message in rx {
+                notify_receivers(message, receivers.clone());
+                let (count, published) = &*thread_pending;
+                if let Ok(mut count) = count.lock() {
+                    *count -= 1;
+                    published.notify_all();
+                }
+            }
+        });
+        Self {
+            sender: tx,
+            pending,
+        }
+    }
+
+    pub fn publish(&self, message: Message) {
+        if let Ok(mut count) = self.pending.0.lock() {
+            *count += 1;
+        }
+        if self.sender.send(message).is_err() {
+            if let Ok(mut count) = self.pending.0.lock() {
+                *count -= 1;
+            }
+        }
+    }
+
+    /**
+    Waits until every message sent so far has been published. Must not be called while holding the
+    bus.
+    */
+    pub fn wait_until_published(&self) {
+        let (count, published) = &*self.pending;
+        if let Ok(count) = count.lock() {
+            drop(published.wait_while(count, |count| *count > 0));
+        }
+    }
+}
+
 pub trait DataSource {
     fn get_receivers(&self) -> Option<Watchers>;
     fn notify_receivers(&self, message: Message) {
-        match &self.get_receivers() {
-            Some(receivers) => {
-                notify_receivers(message, receivers.clone());
-            }
-            None => {}
+        if let Some(receivers) = self.get_receivers() {
+            notify_receivers(message, receivers);
         }
     }
     fn set_receivers(&mut self, receivers: Watchers);
+    /**
+    Stops producing messages until resumed. Events missed in the meantime are not replayed.
+    */
+    fn pause(&self) {}
+    fn resume(&self) {}
+    /**
+    Stops the source for good, waiting for its threads to finish.
+    */
+    fn stop(&mut self) -> Result<(), FsmError> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::data_receiver::DataReceiver;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    struct SlowReceiver(Arc<Mutex<Vec<PathBuf>>>);
+
+    impl DataReceiver for SlowReceiver {
+        fn process_message(&self, message: Message) {
+            if let Message::FileSkipped { file, .. } = message {
+                thread::sleep(Duration::from_millis(5));
+                self.0.lock().unwrap().push(file);
+            }
+        }
+    }
+
+    #[test]
+    fn publishes_messages_in_order() {
+        let received = Arc::new(Mutex::new(vec![]));
+        let receivers: Watchers = Arc::new(Mutex::new(vec![]));
+        receivers
+            .lock()
+            .unwrap()
+            .push(Box::new(SlowReceiver(received.clone())));
+        let publisher = Publisher::new(receivers);
+        let files: Vec<PathBuf> = (0..10)
+            .map(|index| PathBuf::from(format!("/tmp/{}.txt", index)))
+            .collect();
+        for file in &files {
+            publisher.publish(Message::FileSkipped {
+                file: file.clone(),
+                reason: "it is a temporary file".to_string(),
+            });
+        }
+        publisher.wait_until_published();
+        assert_eq!(*received.lock().unwrap(), files);
+        // nothing left to wait for
+        publisher.wait_until_published();
+    }
 }
@@ -1,89 +1,201 @@
-use std::path::Path;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::{sync::mpsc, sync::mpsc::Sender, thread::JoinHandle, time::Duration};
+use std::{
+    sync::mpsc, sync::mpsc::RecvTimeoutError, sync::mpsc::Sender, thread::JoinHandle,
+    time::Duration, time::Instant,
+};
 
 use super::{notify_receivers, DataSource};
+use crate::config::{ConfigManager, DirectoryHandling, WatchRoot};
 use crate::data::LogType;
 use crate::data::{Message, Watchers};
 use crate::error::ErrorType;
 use crate::error::FsmError;
+use crate::exclude::ExcludeSet;
+use crate::schedule::SweepSchedule;
+use chrono::{DateTime, Local};
+
+/**
+Commands sent to the sweeper thread.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SweepCommand {
+    Sweep,
+    Stop,
+}
+
+/**
+Handle for requesting a sweep from outside the sweeper, without waiting for the next scheduled one.
+*/
+#[derive(Debug, Clone)]
+pub struct SweepTrigger {
+    sender: Sender<SweepCommand>,
+}
+
+impl SweepTrigger {
+    pub fn sweep_now(&self) -> Result<(), FsmError> {
+        match self.sender.send(SweepCommand::Sweep) {
+            Err(e) => Err(FsmError::new(ErrorType::SweepError, e.to_string())),
+            Ok(res) => Ok(res),
+        }
+    }
+}
+
+/**
+Flags shared with the sweeper thread. Sweeps in progress stop sending files once either is set.
+*/
+#[derive(Debug, Default)]
+struct SweepFlags {
+    paused: AtomicBool,
+    stopping: AtomicBool,
+}
+
+impl SweepFlags {
+    fn is_halted(&self) -> bool {
+        self.paused.load(Ordering::Relaxed) || self.stopping.load(Ordering::Relaxed)
+    }
+}
 
 pub struct DirectorySweeper {
     repeat_duration: Arc<Mutex<Duration>>,
-    sender: Option<Sender<bool>>,
+    flags: Arc<SweepFlags>,
+    sender: Option<Sender<SweepCommand>>,
     thread_handle: Option<JoinHandle<Result<(), FsmError>>>,
     receivers: Option<Watchers>,
 }
 
-fn sweep(path_to_sweep: impl AsRef<Path>, watchers: Watchers) {
-    if !path_to_sweep.as_ref().exists() {
+/**
+Walks a directory inside a watch root, calling `on_entry` with every file to pick up. Directories are
+descended into, handed over as a unit or skipped depending on the root's `DirectoryHandling`, and
+excluded paths are left out along with their contents. The walk stops early once `is_halted` returns
+true.
+*/
+pub(crate) fn walk_directory(
+    watch_root: &WatchRoot,
+    directory: &Path,
+    exclude: &ExcludeSet,
+    is_halted: &impl Fn() -> bool,
+    on_entry: &mut impl FnMut(io::Result<PathBuf>),
+) {
+    let mut traversal_stack = vec![directory.to_path_buf()];
+    while let Some(curr_directory) = traversal_stack.pop() {
+        if is_halted() {
+            return;
+        }
+        let directory_files = match fs::read_dir(&curr_directory) {
+            Ok(res) => res,
+            Err(e) => {
+                on_entry(Err(e));
+                continue;
+            }
+        };
+        for file in directory_files {
+            if is_halted() {
+                return;
+            }
+            let entry = match file {
+                Ok(res) => res,
+                Err(e) => {
+                    on_entry(Err(e));
+                    continue;
+                }
+            };
+            let entry_path = entry.path();
+            if exclude.is_excluded(watch_root.get_path(), &entry_path)
+                || !watch_root.is_within_depth(&entry_path)
+            {
+                continue;
+            }
+            // symlinks to directories are treated like files rather than followed
+            if !entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+                on_entry(Ok(entry_path));
+                continue;
+            }
+            match watch_root.get_directory_handling() {
+                DirectoryHandling::Descend => traversal_stack.push(entry_path),
+                DirectoryHandling::Move => on_entry(Ok(entry_path)),
+                DirectoryHandling::Skip => (),
+            }
+        }
+    }
+}
+
+fn sweep(
+    watch_root: &WatchRoot,
+    exclude: &ExcludeSet,
+    schedule: Option<&SweepSchedule>,
+    flags: &SweepFlags,
+    watchers: Watchers,
+) {
+    let path_to_sweep = watch_root.get_path();
+    if !path_to_sweep.exists() {
         notify_receivers(
             Message::Log {
-                message: format!("The path {:#?} does not exist.", path_to_sweep.as_ref()),
+                message: format!("The path {:#?} does not exist.", path_to_sweep),
                 message_type: LogType::Error,
             },
             watchers.clone(),
         );
         return;
     }
-    if !path_to_sweep.as_ref().is_dir() {
+    if !path_to_sweep.is_dir() {
         notify_receivers(
             Message::Log {
-                message: format!("The path {:#?} is not a directory.", path_to_sweep.as_ref()),
+                message: format!("The path {:#?} is not a directory.", path_to_sweep),
                 message_type: LogType::Error,
             },
             watchers.clone(),
         );
         return;
     }
-    let directory_files = match std::fs::read_dir(path_to_sweep.as_ref()) {
-        Ok(res) => res,
-        Err(e) => {
-            notify_receivers(
-                Message::Log {
-                    message: e.to_string(),
-                    message_type: LogType::Error,
-                },
-                watchers.clone(),
-            );
-            return;
-        }
-    };
     let mut count = 0;
-    let mut successes = 0;
-
-    for file in directory_files {
-        count += 1;
-        match file {
-            Ok(entry) => {
-                notify_receivers(
-                    Message::FileMove {
-                        file_to_move: entry.path(),
-                    },
-                    watchers.clone(),
-                );
-                successes += 1;
-            }
-            Err(e) => {
-                notify_receivers(
-                    Message::Log {
-                        message: e.to_string(),
-                        message_type: LogType::Error,
-                    },
-                    watchers.clone(),
-                );
-            }
-        };
+    let mut queued = 0;
+
+    let is_halted = || flags.is_halted();
+    walk_directory(
+        watch_root,
+        path_to_sweep,
+        exclude,
+        &is_halted,
+        &mut |file| {
+            count += 1;
+            match file {
+                Ok(file_to_move) => {
+                    notify_receivers(
+                        Message::FileMove {
+                            file_to_move,
+                            schedule: schedule.cloned(),
+                        },
+                        watchers.clone(),
+                    );
+                    queued += 1;
+                }
+                Err(e) => {
+                    notify_receivers(
+                        Message::Log {
+                            message: e.to_string(),
+                            message_type: LogType::Error,
+                        },
+                        watchers.clone(),
+                    );
+                }
+            };
+        },
+    );
+    // the counts of a sweep cut short say little
+    if flags.is_halted() {
+        return;
     }
 
     notify_receivers(
         Message::Log {
             message: format!(
-                "Found {:#?} files in {:?}, {:#?} succesfully moved.",
-                count,
-                path_to_sweep.as_ref(),
-                successes
+                "Found {:#?} files in {:?}, {:#?} queued to be moved.",
+                count, path_to_sweep, queued
             ),
             message_type: LogType::Info,
         },
@@ -91,36 +203,72 @@ fn sweep(path_to_sweep: impl AsRef<Path>, watchers: Watchers) {
     );
 }
 
+/**
+Sweeps the watch roots that are due during the given scheduled sweep, or the unscheduled roots when
+no schedule is given.
+*/
+fn sweep_roots(
+    config_manager: &Arc<Mutex<ConfigManager>>,
+    schedule: Option<&SweepSchedule>,
+    flags: &SweepFlags,
+    watchers: Watchers,
+) -> Result<(), FsmError> {
+    if flags.is_halted() {
+        return Ok(());
+    }
+    let (watch_roots, exclude) = match config_manager.lock() {
+        Err(e) => return Err(FsmError::new(ErrorType::SweepError, e.to_string())),
+        Ok(res) => (res.get_watch_roots().clone(), res.get_exclude().clone()),
+    };
+    // note this locks the corresponding managers
+    for watch_root in watch_roots.iter() {
+        if watch_root.get_schedule().is_none() || watch_root.get_schedule() == schedule {
+            sweep(watch_root, &exclude, schedule, flags, watchers.clone());
+        }
+    }
+    Ok(())
+}
+
+type NextRuns = Vec<(SweepSchedule, Option<DateTime<Local>>)>;
+
+/**
+Returns the next run of each schedule, keeping the ones already known so that edited schedules take
+effect without rescheduling the others.
+*/
+fn get_next_runs(schedules: &[SweepSchedule], previous: &NextRuns) -> NextRuns {
+    let now = Local::now();
+    schedules
+        .iter()
+        .map(|schedule| {
+            let next_run = match previous.iter().find(|(known, _)| known == schedule) {
+                Some((_, next_run)) => *next_run,
+                None => schedule.next_after(&now),
+            };
+            (schedule.clone(), next_run)
+        })
+        .collect()
+}
+
 impl DirectorySweeper {
     pub fn new(repeat_duration: Arc<Mutex<Duration>>) -> Self {
         Self {
             repeat_duration,
+            flags: Arc::new(SweepFlags::default()),
             sender: None,
             thread_handle: None,
             receivers: None,
         }
     }
 
-    pub fn start_sweep<P>(&mut self, path_to_watch: P) -> Result<(), FsmError>
-    where
-        P: AsRef<Path> + Send + 'static,
-    {
+    pub fn start_sweep(
+        &mut self,
+        config_manager: Arc<Mutex<ConfigManager>>,
+    ) -> Result<(), FsmError> {
         if self.sender.is_some() {
             return Ok(());
         }
-        let (tx, rx) = mpsc::channel::<bool>();
+        let (tx, rx) = mpsc::channel::<SweepCommand>();
         let repeat_duration = self.repeat_duration.clone();
-        let repeat_duration = match repeat_duration.lock() {
-            Err(e) => {
-                self.notify_receivers(Message::Log {
-                    message: e.to_string(),
-                    message_type: LogType::Error,
-                });
-                return Err(FsmError::new(ErrorType::SweepError, e.to_string()));
-            }
-            Ok(res) => res,
-        }
-        .clone();
         let receivers = match &self.receivers {
             None => {
                 return Err(FsmError::new(
@@ -131,13 +279,54 @@ impl DirectorySweeper {
             Some(res) => res,
         }
         .clone();
+        let flags = self.flags.clone();
+        flags.stopping.store(false, Ordering::Relaxed);
         let handle = thread::spawn(move || -> Result<(), FsmError> {
-            let mut done = false;
-            while !done {
-                thread::sleep(repeat_duration);
-                // note this locks the corresponding managers
-                let _ = sweep(path_to_watch.as_ref(), receivers.clone());
-                done = rx.try_recv().unwrap_or_default();
+            let mut next_runs = vec![];
+            // the first sweep happens right away, later ones every repeat_duration or on request
+            let mut next_sweep = Instant::now();
+            loop {
+                // both are read again every time, so that config changes apply to the next sweep
+                let schedules = match config_manager.lock() {
+                    Err(e) => return Err(FsmError::new(ErrorType::SweepError, e.to_string())),
+                    Ok(res) => res.get_schedules().clone(),
+                };
+                next_runs = get_next_runs(&schedules, &next_runs);
+                let repeat_duration = match repeat_duration.lock() {
+                    Err(e) => return Err(FsmError::new(ErrorType::SweepError, e.to_string())),
+                    Ok(res) => *res,
+                };
+                let now = Local::now();
+                for (schedule, next_run) in next_runs.iter_mut() {
+                    if next_run.is_some_and(|next_run| next_run <= now) {
+                        sweep_roots(&config_manager, Some(schedule), &flags, receivers.clone())?;
+                        // a run missed or cut short while paused happens once fsm is resumed
+                        if !flags.is_halted() {
+                            *next_run = schedule.next_after(&Local::now());
+                        }
+                    }
+                }
+                if Instant::now() >= next_sweep {
+                    sweep_roots(&config_manager, None, &flags, receivers.clone())?;
+                    next_sweep = Instant::now() + repeat_duration;
+                }
+
+                // wait for whichever comes first, the next regular sweep or a scheduled one
+                let now = Local::now();
+                let timeout = next_runs
+                    .iter()
+                    .filter_map(|(_, next_run)| {
+                        (*next_run)?.signed_duration_since(now).to_std().ok()
+                    })
+                    .fold(
+                        next_sweep.saturating_duration_since(Instant::now()),
+                        Duration::min,
+                    );
+                match rx.recv_timeout(timeout) {
+                    Ok(SweepCommand::Sweep) => next_sweep = Instant::now(),
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Ok(SweepCommand::Stop) | Err(RecvTimeoutError::Disconnected) => break,
+                }
             }
             Ok(())
         });
@@ -148,14 +337,25 @@ impl DirectorySweeper {
         Ok(())
     }
 
+    /**
+    Returns a handle for triggering sweeps, once the sweep has been started.
+    */
+    pub fn get_trigger(&self) -> Option<SweepTrigger> {
+        self.sender.as_ref().map(|sender| SweepTrigger {
+            sender: sender.clone(),
+        })
+    }
+
     pub fn end_sweep(&mut self) -> Result<(), FsmError> {
         if self.thread_handle.is_none() {
             return Ok(());
         }
-        let sender = std::mem::replace(&mut self.sender, None).unwrap();
-        let handle = std::mem::replace(&mut self.thread_handle, None).unwrap();
+        let sender = self.sender.take().unwrap();
+        let handle = self.thread_handle.take().unwrap();
+        // cuts short a sweep that is in progress
+        self.flags.stopping.store(true, Ordering::Relaxed);
 
-        match sender.send(true) {
+        match sender.send(SweepCommand::Stop) {
             Err(e) => {
                 self.notify_receivers(Message::Log {
                     message: format!("{:#?}", &e),
@@ -171,7 +371,7 @@ impl DirectorySweeper {
                     message: format!("{:#?}", &e),
                     message_type: LogType::Error,
                 });
-                return Err(FsmError::new(ErrorType::SweepError, format!("{:#?}", e)));
+                Err(FsmError::new(ErrorType::SweepError, format!("{:#?}", e)))
             }
             Ok(res) => res,
         }
@@ -185,4 +385,13 @@ impl DataSource for DirectorySweeper {
     fn set_receivers(&mut self, receivers: Watchers) {
         self.receivers = Some(receivers);
     }
+    fn pause(&self) {
+        self.flags.paused.store(true, Ordering::Relaxed);
+    }
+    fn resume(&self) {
+        self.flags.paused.store(false, Ordering::Relaxed);
+    }
+    fn stop(&mut self) -> Result<(), FsmError> {
+        self.end_sweep()
+    }
 }
@@ -1,98 +1,356 @@
+use crate::config::{find_watch_root, DirectoryHandling, WatchRoot};
 use crate::data::LogType;
 use crate::data::Message;
+use crate::data::RecentWrites;
 use crate::data::Watchers;
 use crate::error::ErrorType;
 use crate::error::FsmError;
+use crate::exclude::ExcludeSet;
 use crate::ConfigManager;
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-use notify::{event::CreateKind, EventKind, RecursiveMode};
-use notify_debouncer_full::{new_debouncer, notify::Watcher, DebounceEventResult};
+use notify::{
+    event::{ModifyKind, RenameMode},
+    Event, EventKind, PollWatcher, RecommendedWatcher, RecursiveMode,
+};
+use notify_debouncer_full::{
+    new_debouncer_opt, notify::Watcher, DebounceEventResult, Debouncer, FileIdMap,
+};
+
+use serde::{Deserialize, Serialize};
 
 use super::notify_receivers;
+use super::sweep::walk_directory;
 use super::DataSource;
 
 extern crate notify;
 extern crate notify_debouncer_full;
 
+const DEBOUNCE_TIMEOUT: Duration = Duration::from_secs(1);
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/**
+Debounced watcher backing a `DirectoryWatcher`. The native variant uses the platform's recommended
+notify backend (inotify on Linux), with polling used as a fallback when the native backend cannot
+be set up (e.g. the inotify watch limit is exhausted).
+*/
+enum DebouncedWatcher {
+    Native(Debouncer<RecommendedWatcher, FileIdMap>),
+    Polling(Debouncer<PollWatcher, FileIdMap>),
+}
+
+impl DebouncedWatcher {
+    /**
+    Stops watching, waiting for the events being handled to finish.
+    */
+    fn stop(self) {
+        match self {
+            DebouncedWatcher::Native(debouncer) => debouncer.stop(),
+            DebouncedWatcher::Polling(debouncer) => debouncer.stop(),
+        }
+    }
+}
+
 pub struct DirectoryWatcher {
-    debounced_watcher: notify_debouncer_full::Debouncer<
-        notify::ReadDirectoryChangesWatcher,
-        notify_debouncer_full::FileIdMap,
-    >,
+    // None once the watcher is stopped
+    debounced_watcher: Option<DebouncedWatcher>,
+    settings: Arc<WatchSettings>,
     receivers: Option<Watchers>,
 }
 
+/**
+Kinds of events that can make the watcher pick up a file. `create` covers newly created files,
+`rename` covers files moved or renamed into the watched directory (e.g. a completed download) and
+`modify` covers changes to the contents of existing files.
+*/
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum WatchEventKind {
+    Create,
+    Rename,
+    Modify,
+}
+
+pub const DEFAULT_WATCH_EVENTS: [WatchEventKind; 2] =
+    [WatchEventKind::Create, WatchEventKind::Rename];
+
+/**
+Settings shared with the debouncer callback.
+*/
+struct WatchSettings {
+    watch_events: HashSet<WatchEventKind>,
+    // destinations of fsm's own moves, except those containing a watched directory
+    ignored_roots: Vec<PathBuf>,
+    recent_writes: Arc<Mutex<RecentWrites>>,
+    watch_roots: Vec<WatchRoot>,
+    exclude: ExcludeSet,
+    paused: AtomicBool,
+}
+
+/**
+Returns the kind of the event along with the path of the file that now exists, if the event means
+that a file appeared or changed.
+*/
+fn get_new_file(event: &Event) -> Option<(WatchEventKind, &PathBuf)> {
+    match event.kind {
+        EventKind::Create(_) => Some((WatchEventKind::Create, event.paths.first()?)),
+        EventKind::Modify(ModifyKind::Name(RenameMode::To)) => {
+            Some((WatchEventKind::Rename, event.paths.first()?))
+        }
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {
+            Some((WatchEventKind::Rename, event.paths.last()?))
+        }
+        // some backends cannot tell the two sides of a rename apart
+        EventKind::Modify(ModifyKind::Name(RenameMode::Any)) => event
+            .paths
+            .last()
+            .filter(|path| path.exists())
+            .map(|path| (WatchEventKind::Rename, path)),
+        EventKind::Modify(ModifyKind::Name(_)) => None,
+        EventKind::Modify(_) => event
+            .paths
+            .first()
+            .filter(|path| path.is_file())
+            .map(|path| (WatchEventKind::Modify, path)),
+        _ => None,
+    }
+}
+
+fn handle_events(result: DebounceEventResult, receivers: Watchers, settings: &WatchSettings) {
+    if settings.paused.load(Ordering::Relaxed) {
+        return;
+    }
+    match result {
+        Ok(events) => {
+            let mut seen = HashSet::new();
+            for event in events.into_iter() {
+                let (kind, filepath) = match get_new_file(&event.event) {
+                    Some(res) => res,
+                    None => continue,
+                };
+                if !settings.watch_events.contains(&kind) || !seen.insert(filepath.clone()) {
+                    continue;
+                }
+                // ignore events caused by fsm's own moves
+                if settings
+                    .ignored_roots
+                    .iter()
+                    .any(|root| filepath.starts_with(root))
+                {
+                    continue;
+                }
+                let written_by_fsm = match settings.recent_writes.lock() {
+                    Err(_) => false,
+                    Ok(mut res) => res.contains(filepath),
+                };
+                if written_by_fsm {
+                    continue;
+                }
+                let watch_root = match find_watch_root(&settings.watch_roots, filepath) {
+                    Some(res) => res,
+                    None => continue,
+                };
+                // roots on a schedule are only swept at the scheduled times
+                if watch_root.get_schedule().is_some()
+                    || settings
+                        .exclude
+                        .is_excluded(watch_root.get_path(), filepath)
+                    || !watch_root.is_within_depth(filepath)
+                {
+                    continue;
+                }
+                if !filepath.is_dir() || filepath.is_symlink() {
+                    notify_receivers(
+                        Message::FileMove {
+                            file_to_move: filepath.to_owned(),
+                            schedule: None,
+                        },
+                        receivers.clone(),
+                    );
+                    continue;
+                }
+                match watch_root.get_directory_handling() {
+                    // files already inside a directory moved into the root produce no events
+                    DirectoryHandling::Descend => {
+                        let is_paused = || settings.paused.load(Ordering::Relaxed);
+                        walk_directory(
+                            watch_root,
+                            filepath,
+                            &settings.exclude,
+                            &is_paused,
+                            &mut |file| {
+                                if let Ok(file_to_move) = file {
+                                    if seen.insert(file_to_move.clone()) {
+                                        notify_receivers(
+                                            Message::FileMove {
+                                                file_to_move,
+                                                schedule: None,
+                                            },
+                                            receivers.clone(),
+                                        );
+                                    }
+                                }
+                            },
+                        )
+                    }
+                    DirectoryHandling::Move => notify_receivers(
+                        Message::FileMove {
+                            file_to_move: filepath.to_owned(),
+                            schedule: None,
+                        },
+                        receivers.clone(),
+                    ),
+                    DirectoryHandling::Skip => (),
+                }
+            }
+        }
+        Err(errors) => {
+            for err in errors {
+                let message = match err.kind {
+                    notify::ErrorKind::MaxFilesWatch => format!(
+                        "The OS file watch limit was reached, some paths ({:?}) will not be watched. On Linux, raise fs.inotify.max_user_watches.",
+                        err.paths
+                    ),
+                    _ => err.to_string(),
+                };
+                notify_receivers(
+                    Message::Log {
+                        message,
+                        message_type: LogType::Error,
+                    },
+                    receivers.clone(),
+                );
+            }
+        }
+    };
+}
+
+fn watch_directories<W>(
+    watch_roots: &[WatchRoot],
+    receivers: Watchers,
+    settings: Arc<WatchSettings>,
+    config: notify::Config,
+) -> Result<Debouncer<W, FileIdMap>, notify::Error>
+where
+    W: Watcher,
+{
+    let mut debouncer = new_debouncer_opt::<_, W, FileIdMap>(
+        DEBOUNCE_TIMEOUT,
+        None,
+        move |result: DebounceEventResult| handle_events(result, receivers.clone(), &settings),
+        FileIdMap::new(),
+        config,
+    )?;
+    for watch_root in watch_roots {
+        let recursive_mode = match watch_root.get_max_depth() {
+            Some(0) => RecursiveMode::NonRecursive,
+            _ => RecursiveMode::Recursive,
+        };
+        debouncer
+            .watcher()
+            .watch(watch_root.get_path(), recursive_mode)?;
+        debouncer
+            .cache()
+            .add_root(watch_root.get_path(), recursive_mode);
+    }
+    Ok(debouncer)
+}
+
 impl DirectoryWatcher {
     pub fn new(
         config_manager: Arc<Mutex<ConfigManager>>,
         receivers: Watchers,
+        recent_writes: Arc<Mutex<RecentWrites>>,
     ) -> Result<Self, FsmError> {
-        let new_receivers = receivers.clone();
-        let mut debouncer = match new_debouncer(
-            Duration::from_secs(1),
-            None,
-            move |result: DebounceEventResult| {
-                match result {
-                    Ok(events) => {
-                        for event in events.into_iter() {
-                            let event = event.event;
-                            if event.kind != EventKind::Create(CreateKind::Any) {
-                                continue;
-                            }
-                            let filepath = match event.paths.get(0) {
-                                Some(res) => res,
-                                None => continue,
-                            }
-                            .to_owned();
-                            notify_receivers(
-                                Message::FileMove {
-                                    file_to_move: filepath,
-                                },
-                                receivers.clone(),
-                            );
-                        }
-                    }
-                    Err(errors) => {
-                        for err in errors {
-                            notify_receivers(
-                                Message::Log {
-                                    message: err.to_string(),
-                                    message_type: LogType::Error,
-                                },
-                                receivers.clone(),
-                            );
+        match config_manager.lock() {
+            Ok(res) => Self::from_config(&res, receivers, recent_writes),
+            Err(e) => Err(FsmError::new(ErrorType::WatcherError, e.to_string())),
+        }
+    }
+
+    /**
+    Starts watching the roots of a config that is not shared yet, e.g. one about to be swapped in.
+    */
+    pub fn from_config(
+        config_manager: &ConfigManager,
+        receivers: Watchers,
+        recent_writes: Arc<Mutex<RecentWrites>>,
+    ) -> Result<Self, FsmError> {
+        let watch_roots = config_manager.get_watch_roots();
+        let mut managed_paths: Vec<PathBuf> = watch_roots
+            .iter()
+            .map(|watch_root| watch_root.get_managed_path().clone())
+            .collect();
+        managed_paths.push(config_manager.get_manage_path().clone());
+        let settings = Arc::new(WatchSettings {
+            watch_events: config_manager.get_watch_events().iter().copied().collect(),
+            ignored_roots: managed_paths
+                .into_iter()
+                .filter(|managed_path| {
+                    !watch_roots
+                        .iter()
+                        .any(|watch_root| watch_root.get_path().starts_with(managed_path))
+                })
+                .collect(),
+            recent_writes,
+            watch_roots: watch_roots.clone(),
+            exclude: config_manager.get_exclude().clone(),
+            paused: AtomicBool::new(false),
+        });
+        let watch_paths: Vec<&PathBuf> = watch_roots
+            .iter()
+            .map(|watch_root| watch_root.get_path())
+            .collect();
+
+        let debounced_watcher = match watch_directories::<RecommendedWatcher>(
+            watch_roots,
+            receivers.clone(),
+            settings.clone(),
+            notify::Config::default(),
+        ) {
+            Ok(res) => DebouncedWatcher::Native(res),
+            Err(e) => match e.kind {
+                notify::ErrorKind::MaxFilesWatch => {
+                    notify_receivers(
+                        Message::Log {
+                            message: format!(
+                                "The OS file watch limit was reached while watching {:?}, falling back to polling every {:?}. On Linux, raise fs.inotify.max_user_watches to use inotify.",
+                                watch_paths, POLL_INTERVAL
+                            ),
+                            message_type: LogType::Warning,
+                        },
+                        receivers.clone(),
+                    );
+                    match watch_directories::<PollWatcher>(
+                        watch_roots,
+                        receivers.clone(),
+                        settings.clone(),
+                        notify::Config::default().with_poll_interval(POLL_INTERVAL),
+                    ) {
+                        Ok(res) => DebouncedWatcher::Polling(res),
+                        Err(e) => {
+                            return Err(FsmError::new(ErrorType::WatcherError, e.to_string()))
                         }
                     }
-                };
+                }
+                _ => return Err(FsmError::new(ErrorType::WatcherError, e.to_string())),
             },
-        ) {
-            Err(e) => {
-                return Err(FsmError::new(ErrorType::WatcherError, e.to_string()));
-            }
-            Ok(res) => res,
         };
 
-        let config_manager = config_manager.clone();
-        let config_manager = match config_manager.lock() {
-            Ok(res) => res,
-            Err(e) => return Err(FsmError::new(ErrorType::WatcherError, e.to_string())),
-        };
-
-        let watch_path = config_manager.get_watch_path();
-
-        let _ = debouncer
-            .watcher()
-            .watch(watch_path, RecursiveMode::Recursive);
-        let _ = debouncer
-            .cache()
-            .add_root(watch_path, RecursiveMode::Recursive);
         Ok(Self {
-            debounced_watcher: debouncer,
-            receivers: Some(new_receivers),
+            debounced_watcher: Some(debounced_watcher),
+            settings,
+            receivers: Some(receivers),
         })
     }
+
+    pub fn is_polling(&self) -> bool {
+        matches!(self.debounced_watcher, Some(DebouncedWatcher::Polling(_)))
+    }
 }
 
 impl DataSource for DirectoryWatcher {
@@ -102,4 +360,16 @@ impl DataSource for DirectoryWatcher {
     fn set_receivers(&mut self, receivers: Watchers) {
         self.receivers = Some(receivers);
     }
+    fn pause(&self) {
+        self.settings.paused.store(true, Ordering::Relaxed);
+    }
+    fn resume(&self) {
+        self.settings.paused.store(false, Ordering::Relaxed);
+    }
+    fn stop(&mut self) -> Result<(), FsmError> {
+        if let Some(debounced_watcher) = self.debounced_watcher.take() {
+            debounced_watcher.stop();
+        }
+        Ok(())
+    }
 }
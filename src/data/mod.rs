@@ -1,8 +1,16 @@
 use std::{
-    path::PathBuf,
-    sync::{Arc, Mutex},
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
 };
 
+use crate::error::{ErrorType, FsmError};
+use crate::filter::FileAction;
+use crate::schedule::SweepSchedule;
 use data_receiver::DataReceiver;
 use data_source::DataSource;
 
@@ -12,6 +20,34 @@ pub mod data_source;
 type Producers = Arc<Mutex<Vec<Box<dyn DataSource + Send>>>>;
 type Watchers = Arc<Mutex<Vec<Box<dyn DataReceiver + Send>>>>;
 
+/**
+How long a path written by fsm is remembered, so that the watcher can ignore the events it causes.
+*/
+const RECENT_WRITE_TTL: Duration = Duration::from_secs(10);
+
+/**
+Paths recently written by fsm itself (move destinations, symlinks left behind, ...).
+*/
+#[derive(Debug, Default)]
+pub struct RecentWrites {
+    paths: HashMap<PathBuf, Instant>,
+}
+
+impl RecentWrites {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn insert(&mut self, path: impl AsRef<Path>) {
+        self.paths
+            .insert(path.as_ref().to_path_buf(), Instant::now());
+    }
+    pub fn contains(&mut self, path: impl AsRef<Path>) -> bool {
+        self.paths
+            .retain(|_, written| written.elapsed() < RECENT_WRITE_TTL);
+        self.paths.contains_key(path.as_ref())
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum LogType {
     Info,
@@ -23,6 +59,23 @@ pub enum LogType {
 pub enum Message {
     FileMove {
         file_to_move: PathBuf,
+        // set when the file was found by a scheduled sweep, enabling the filters on that schedule
+        schedule: Option<SweepSchedule>,
+    },
+    FileMoved {
+        from: PathBuf,
+        to: PathBuf,
+        // index of the filter that matched the file
+        filter: usize,
+        action: FileAction,
+    },
+    FileSkipped {
+        file: PathBuf,
+        reason: String,
+    },
+    FileMoveFailed {
+        file: PathBuf,
+        error: String,
     },
     Log {
         message: String,
@@ -30,11 +83,39 @@ pub enum Message {
     },
 }
 
+/**
+Counts of the move outcomes reported on the bus.
+*/
+#[derive(Debug, Default)]
+pub struct MoveStatistics {
+    pub moved: AtomicUsize,
+    pub skipped: AtomicUsize,
+    pub failed: AtomicUsize,
+}
+
+impl MoveStatistics {
+    pub fn get_moved(&self) -> usize {
+        self.moved.load(Ordering::Relaxed)
+    }
+    pub fn get_skipped(&self) -> usize {
+        self.skipped.load(Ordering::Relaxed)
+    }
+    pub fn get_failed(&self) -> usize {
+        self.failed.load(Ordering::Relaxed)
+    }
+}
+
 pub struct MessageManager {
     sources: Producers,
     receivers: Watchers,
 }
 
+impl Default for MessageManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl MessageManager {
     pub fn new() -> Self {
         Self {
@@ -70,4 +151,53 @@ impl MessageManager {
     pub fn get_receivers(&self) -> Watchers {
         self.receivers.clone()
     }
+    pub fn pause_sources(&self) {
+        if let Ok(sources) = self.sources.lock() {
+            for source in sources.iter() {
+                source.pause();
+            }
+        }
+    }
+    pub fn resume_sources(&self) {
+        if let Ok(sources) = self.sources.lock() {
+            for source in sources.iter() {
+                source.resume();
+            }
+        }
+    }
+    /**
+    Stops every source, returning the first error encountered.
+    */
+    pub fn stop_sources(&self) -> Result<(), FsmError> {
+        let mut sources = match self.sources.lock() {
+            Err(e) => return Err(FsmError::new(ErrorType::ApplicationError, e.to_string())),
+            Ok(res) => res,
+        };
+        let mut result = Ok(());
+        for source in sources.iter_mut() {
+            if let Err(e) = source.stop() {
+                result = result.and(Err(e));
+            }
+        }
+        result
+    }
+    /**
+    Stops every source and removes it, so that new ones can take over.
+    */
+    pub fn remove_sources(&self) -> Result<(), FsmError> {
+        let stopped = self.stop_sources();
+        if let Ok(mut sources) = self.sources.lock() {
+            sources.clear();
+        }
+        stopped
+    }
+    pub fn shutdown_receivers(&self) {
+        let mut receivers = match self.receivers.lock() {
+            Err(_) => return,
+            Ok(res) => res,
+        };
+        for receiver in receivers.iter_mut() {
+            receiver.shutdown();
+        }
+    }
 }
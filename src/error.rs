@@ -14,6 +14,9 @@ pub enum ErrorType {
     ReaderError,
     WatcherError,
     SweepError,
+    JournalError,
+    NoMatchError,     // a file not matching any filter
+    OffScheduleError, // a file matching a filter outside of its schedule
     ApplicationError, // generic error type
 }
 
@@ -33,6 +36,9 @@ impl FsmError {
     pub fn get_error_message(&self) -> &str {
         &self.message
     }
+    pub fn get_error_type(&self) -> &ErrorType {
+        &self.error_type
+    }
 }
 
 // generic errors
@@ -66,3 +72,27 @@ impl From<serde_json::Error> for FsmError {
         Self::new(ErrorType::ApplicationError, err.to_string())
     }
 }
+
+impl From<toml::de::Error> for FsmError {
+    fn from(err: toml::de::Error) -> Self {
+        Self::new(ErrorType::ApplicationError, err.to_string())
+    }
+}
+
+impl From<toml::ser::Error> for FsmError {
+    fn from(err: toml::ser::Error) -> Self {
+        Self::new(ErrorType::ApplicationError, err.to_string())
+    }
+}
+
+impl From<serde_saphyr::Error> for FsmError {
+    fn from(err: serde_saphyr::Error) -> Self {
+        Self::new(ErrorType::ApplicationError, err.to_string())
+    }
+}
+
+impl From<serde_saphyr::ser::Error> for FsmError {
+    fn from(err: serde_saphyr::ser::Error) -> Self {
+        Self::new(ErrorType::ApplicationError, err.to_string())
+    }
+}
@@ -0,0 +1,125 @@
+use std::path::Path;
+
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
+
+use crate::error::{ErrorType, FsmError};
+
+/**
+Glob patterns for paths that are never picked up. Patterns without a `/` (e.g. `node_modules`) match
+any single file or directory name, while the others (e.g. `docs/drafts`) are matched against the
+path relative to its watch root. Anything inside an excluded directory is excluded as well.
+*/
+#[derive(Debug, Clone)]
+pub struct ExcludeSet {
+    patterns: Vec<String>,
+    name_globs: GlobSet,
+    path_globs: GlobSet,
+}
+
+fn build_glob(pattern: &str) -> Result<Glob, FsmError> {
+    match GlobBuilder::new(pattern).literal_separator(true).build() {
+        Ok(res) => Ok(res),
+        Err(e) => Err(FsmError::new(
+            ErrorType::ConfigError,
+            format!("Invalid exclude pattern {:?}: {}", pattern, e),
+        )),
+    }
+}
+
+fn build_set(builder: GlobSetBuilder) -> Result<GlobSet, FsmError> {
+    match builder.build() {
+        Ok(res) => Ok(res),
+        Err(e) => Err(FsmError::new(ErrorType::ConfigError, e.to_string())),
+    }
+}
+
+impl ExcludeSet {
+    pub fn new(patterns: Vec<String>) -> Result<Self, FsmError> {
+        let mut name_globs = GlobSetBuilder::new();
+        let mut path_globs = GlobSetBuilder::new();
+        for pattern in patterns.iter() {
+            let pattern = pattern.trim_end_matches('/');
+            match pattern.contains('/') {
+                true => path_globs.add(build_glob(pattern.trim_start_matches('/'))?),
+                false => name_globs.add(build_glob(pattern)?),
+            };
+        }
+        Ok(Self {
+            patterns,
+            name_globs: build_set(name_globs)?,
+            path_globs: build_set(path_globs)?,
+        })
+    }
+
+    pub fn get_patterns(&self) -> &Vec<String> {
+        &self.patterns
+    }
+
+    /**
+    Whether the path, or one of its parents below `root`, is excluded.
+    */
+    pub fn is_excluded(&self, root: &Path, path: &Path) -> bool {
+        let relative_path = match path.strip_prefix(root) {
+            Ok(res) => res,
+            Err(_) => return false,
+        };
+        relative_path.ancestors().any(|ancestor| {
+            !ancestor.as_os_str().is_empty()
+                && (self.path_globs.is_match(ancestor)
+                    || ancestor
+                        .file_name()
+                        .is_some_and(|name| self.name_globs.is_match(name)))
+        })
+    }
+}
+
+impl Default for ExcludeSet {
+    fn default() -> Self {
+        Self {
+            patterns: vec![],
+            name_globs: GlobSet::empty(),
+            path_globs: GlobSet::empty(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exclude_set(patterns: &[&str]) -> ExcludeSet {
+        ExcludeSet::new(patterns.iter().map(|pattern| pattern.to_string()).collect()).unwrap()
+    }
+
+    #[test]
+    fn excludes_names_at_any_depth() {
+        let exclude = exclude_set(&["node_modules", "*.tmp"]);
+        let root = Path::new("/watched");
+        assert!(exclude.is_excluded(root, Path::new("/watched/node_modules")));
+        assert!(exclude.is_excluded(root, Path::new("/watched/app/node_modules/lib/index.js")));
+        assert!(exclude.is_excluded(root, Path::new("/watched/a/b/report.tmp")));
+        assert!(!exclude.is_excluded(root, Path::new("/watched/app/src/index.js")));
+        assert!(!exclude.is_excluded(root, Path::new("/watched/node_modules_backup")));
+    }
+
+    #[test]
+    fn excludes_paths_relative_to_the_root() {
+        let exclude = exclude_set(&["docs/drafts/", "**/.git/**", "/build"]);
+        let root = Path::new("/watched");
+        assert!(exclude.is_excluded(root, Path::new("/watched/docs/drafts/a.txt")));
+        assert!(!exclude.is_excluded(root, Path::new("/watched/other/docs/drafts/a.txt")));
+        assert!(exclude.is_excluded(root, Path::new("/watched/repo/.git/HEAD")));
+        assert!(exclude.is_excluded(root, Path::new("/watched/build/out.o")));
+        assert!(!exclude.is_excluded(root, Path::new("/watched/src/build")));
+        // the root itself and paths outside of it are never excluded
+        assert!(!exclude_set(&["watched"]).is_excluded(root, root));
+        assert!(!exclude.is_excluded(root, Path::new("/elsewhere/build/out.o")));
+    }
+
+    #[test]
+    fn rejects_invalid_patterns() {
+        let error = ExcludeSet::new(vec!["a[".to_string()]).unwrap_err();
+        assert!(matches!(error.get_error_type(), ErrorType::ConfigError));
+        assert!(!ExcludeSet::default().is_excluded(Path::new("/"), Path::new("/a")));
+    }
+}
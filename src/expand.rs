@@ -0,0 +1,164 @@
+use std::{env, path::PathBuf};
+
+use crate::error::{ErrorType, FsmError};
+
+type DirectoryLookup = fn() -> Option<PathBuf>;
+
+/**
+Directory tokens that can be used in configured paths, along with the user directory they stand for.
+*/
+const DIRECTORY_TOKENS: [(&str, DirectoryLookup); 12] = [
+    ("home", dirs::home_dir),
+    ("desktop", dirs::desktop_dir),
+    ("documents", dirs::document_dir),
+    ("downloads", dirs::download_dir),
+    ("music", dirs::audio_dir),
+    ("pictures", dirs::picture_dir),
+    ("videos", dirs::video_dir),
+    ("public", dirs::public_dir),
+    ("templates", dirs::template_dir),
+    ("config", dirs::config_dir),
+    ("data", dirs::data_dir),
+    ("cache", dirs::cache_dir),
+];
+
+fn lookup_directory(token: &str, get_directory: DirectoryLookup) -> Result<String, FsmError> {
+    match get_directory() {
+        Some(res) => Ok(res.to_string_lossy().into_owned()),
+        None => Err(FsmError::new(
+            ErrorType::ConfigError,
+            format!("The {{{}}} directory is not known on this system.", token),
+        )),
+    }
+}
+
+/**
+Returns the user directory a token stands for, or `None` if it is not a directory token.
+*/
+fn get_directory(token: &str) -> Option<Result<String, FsmError>> {
+    let (_, get_directory) = DIRECTORY_TOKENS.iter().find(|(name, _)| *name == token)?;
+    Some(lookup_directory(token, *get_directory))
+}
+
+fn get_variable(name: &str) -> Result<String, FsmError> {
+    match env::var(name) {
+        Ok(res) => Ok(res),
+        Err(e) => Err(FsmError::new(
+            ErrorType::ConfigError,
+            format!("Cannot expand ${}: {}.", name, e),
+        )),
+    }
+}
+
+fn is_variable_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/**
+Expands a configured path. A leading `~` stands for the home directory, `$VAR` and `${VAR}` for
+environment variables and tokens such as `{downloads}` or `{documents}` for the user's directories
+(the XDG user directories on Linux). `$$` stands for a literal `$`, e.g. `C:\$$Recycle.Bin`.
+Anything else, including other names in braces, is kept as written.
+*/
+pub fn expand_path(path: &str) -> Result<String, FsmError> {
+    let mut expanded = String::new();
+    let mut rest = path;
+    if let Some(after) = rest.strip_prefix('~') {
+        if after.is_empty() || after.starts_with(['/', '\\']) {
+            expanded.push_str(&lookup_directory("home", dirs::home_dir)?);
+            rest = after;
+        }
+    }
+    while let Some(start) = rest.find(['$', '{']) {
+        expanded.push_str(&rest[..start]);
+        let token = &rest[start..];
+        let (value, length) = if token.starts_with("$$") {
+            ("$".to_string(), 2)
+        } else if let Some(braced) = token.strip_prefix("${") {
+            match braced.find('}') {
+                Some(end) => (get_variable(&braced[..end])?, end + 3),
+                None => (token[..2].to_string(), 2),
+            }
+        } else if let Some(variable) = token.strip_prefix('$') {
+            let end = variable
+                .find(|c| !is_variable_char(c))
+                .unwrap_or(variable.len());
+            match end {
+                // a lone `$` is kept
+                0 => ("$".to_string(), 1),
+                _ => (get_variable(&variable[..end])?, end + 1),
+            }
+        } else {
+            let name = &token[1..];
+            // names that are not directory tokens, e.g. `{backup}`, are kept
+            match name
+                .find('}')
+                .and_then(|end| Some((get_directory(&name[..end])?, end)))
+            {
+                Some((directory, end)) => (directory?, end + 2),
+                None => ("{".to_string(), 1),
+            }
+        };
+        expanded.push_str(&value);
+        rest = &token[length..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn home() -> String {
+        dirs::home_dir().unwrap().to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn expands_home_and_directories() {
+        assert_eq!(expand_path("~").unwrap(), home());
+        assert_eq!(
+            expand_path("~/Sorted").unwrap(),
+            format!("{}/Sorted", home())
+        );
+        assert_eq!(expand_path("{home}/x").unwrap(), format!("{}/x", home()));
+        // only a leading `~` on its own stands for the home directory
+        assert_eq!(expand_path("~user/x").unwrap(), "~user/x");
+        assert_eq!(expand_path("/tmp/~").unwrap(), "/tmp/~");
+    }
+
+    #[test]
+    fn expands_variables() {
+        let name = format!("FSM_EXPAND_TEST_{}", std::process::id());
+        env::set_var(&name, "/srv/files");
+        assert_eq!(
+            expand_path(&format!("${}/in", name)).unwrap(),
+            "/srv/files/in"
+        );
+        assert_eq!(
+            expand_path(&format!("${{{}}}_old", name)).unwrap(),
+            "/srv/files_old"
+        );
+        env::remove_var(&name);
+        let error = expand_path(&format!("${}/in", name)).unwrap_err();
+        assert!(matches!(error.get_error_type(), ErrorType::ConfigError));
+    }
+
+    #[test]
+    fn keeps_everything_else_as_written() {
+        for path in [
+            "D:\\{backup}\\x",
+            "/tmp/{}/{unclosed",
+            "/tmp/{home dir}",
+            "/tmp/costs $",
+            "/tmp/${unclosed",
+        ] {
+            assert_eq!(expand_path(path).unwrap(), path);
+        }
+        assert_eq!(
+            expand_path("C:\\$$Recycle.Bin\\x").unwrap(),
+            "C:\\$Recycle.Bin\\x"
+        );
+        assert_eq!(expand_path("/tmp/$$$$").unwrap(), "/tmp/$$");
+    }
+}
@@ -1,46 +1,88 @@
 use std::{
     collections::{HashMap, VecDeque},
     fs,
-    path::PathBuf,
+    path::{Component, Path, PathBuf},
 };
 
-use crate::error::FsmError;
+use crate::error::{ErrorType, FsmError};
 use crate::reader::DirectoryEntry;
 
 #[derive(Debug)]
 pub struct FilepathManager {
     directory_mapping: HashMap<String, PathBuf>,
+    // mappings of any additional managed roots, keyed by the managed root
+    root_mappings: HashMap<PathBuf, HashMap<String, PathBuf>>,
+}
+
+fn build_directory_mapping(
+    base_managed_directory: &Path,
+    directory_structure: &DirectoryEntry,
+    create_missing: bool,
+) -> Result<HashMap<String, PathBuf>, FsmError> {
+    let mut traversal_queue: VecDeque<(&DirectoryEntry, PathBuf)> = VecDeque::from([(
+        directory_structure,
+        PathBuf::from(base_managed_directory).join("categorized"),
+    )]);
+    let mut directory_mapping: HashMap<String, PathBuf> = HashMap::new();
+
+    while let Some((entry, curr_path)) = traversal_queue.pop_front() {
+        if create_missing && !curr_path.exists() {
+            fs::create_dir(&curr_path)?;
+        }
+        match entry {
+            DirectoryEntry::LeafDirectory(key_name) => {
+                // TODO - avoid using clone here
+                directory_mapping.insert(key_name.clone(), curr_path);
+            }
+            DirectoryEntry::ParentDirectory(children) => {
+                // create children entries
+                for (key, value) in children.iter() {
+                    traversal_queue.push_back((value, curr_path.join(key)));
+                }
+            }
+        }
+    }
+    Ok(directory_mapping)
 }
 
 impl FilepathManager {
     pub fn new(
-        base_managed_directory: &PathBuf,
+        base_managed_directory: &Path,
         directory_structure: &DirectoryEntry,
+        create_missing: bool,
     ) -> Result<Self, FsmError> {
-        let mut traversal_queue: VecDeque<(&DirectoryEntry, PathBuf)> = VecDeque::from([(
-            directory_structure,
-            PathBuf::from(base_managed_directory).join("categorized"),
-        )]);
-        let mut directory_mapping: HashMap<String, PathBuf> = HashMap::new();
-
-        while let Some((entry, curr_path)) = traversal_queue.pop_front() {
-            if !curr_path.exists() {
-                fs::create_dir(&curr_path)?;
-            }
-            match entry {
-                DirectoryEntry::LeafDirectory(key_name) => {
-                    // TODO - avoid using clone here
-                    directory_mapping.insert(key_name.clone(), curr_path);
-                }
-                DirectoryEntry::ParentDirectory(children) => {
-                    // create children entries
-                    for (key, value) in children.into_iter() {
-                        traversal_queue.push_back((value, curr_path.join(key)));
-                    }
-                }
-            }
+        Ok(Self {
+            directory_mapping: build_directory_mapping(
+                base_managed_directory,
+                directory_structure,
+                create_missing,
+            )?,
+            root_mappings: HashMap::new(),
+        })
+    }
+    /**
+    Builds the managed directory structure under an additional managed root.
+    */
+    pub fn add_managed_root(
+        &mut self,
+        base_managed_directory: &Path,
+        directory_structure: &DirectoryEntry,
+        create_missing: bool,
+    ) -> Result<(), FsmError> {
+        let directory_mapping =
+            build_directory_mapping(base_managed_directory, directory_structure, create_missing)?;
+        self.root_mappings
+            .insert(base_managed_directory.to_path_buf(), directory_mapping);
+        Ok(())
+    }
+    /**
+    Looks up a directory key under the given managed root, falling back to the primary one.
+    */
+    pub fn get_in_root(&self, managed_root: &Path, directory_key: &str) -> Option<&PathBuf> {
+        match self.root_mappings.get(managed_root) {
+            Some(directory_mapping) => directory_mapping.get(directory_key),
+            None => self.get(directory_key),
         }
-        Ok(Self { directory_mapping })
     }
     pub fn get(&self, directory_key: &str) -> Option<&PathBuf> {
         self.directory_mapping.get(directory_key)
@@ -49,3 +91,116 @@ impl FilepathManager {
         &self.directory_mapping
     }
 }
+
+/**
+Renders a destination template such as `{term}/{course}` into a relative path, substituting each
+`{name}` placeholder with its value. The rendered path may not escape the directory it is joined to.
+*/
+pub fn render_path_template(
+    template: &str,
+    placeholders: &HashMap<String, String>,
+) -> Result<PathBuf, FsmError> {
+    let mut rendered = String::new();
+    let mut remaining = template;
+    while let Some(start) = remaining.find('{') {
+        rendered.push_str(&remaining[..start]);
+        let end = match remaining[start..].find('}') {
+            Some(res) => start + res,
+            None => {
+                return Err(FsmError::new(
+                    ErrorType::FilepathError,
+                    format!(
+                        "Unclosed placeholder in destination template {:?}.",
+                        template
+                    ),
+                ))
+            }
+        };
+        let name = &remaining[start + 1..end];
+        match placeholders.get(name) {
+            Some(value) => rendered.push_str(value),
+            None => {
+                return Err(FsmError::new(
+                    ErrorType::FilepathError,
+                    format!(
+                        "No value for placeholder {{{}}} in destination template {:?}.",
+                        name, template
+                    ),
+                ))
+            }
+        }
+        remaining = &remaining[end + 1..];
+    }
+    rendered.push_str(remaining);
+
+    let rendered = PathBuf::from(rendered);
+    if !rendered
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return Err(FsmError::new(
+            ErrorType::FilepathError,
+            format!(
+                "Destination template {:?} rendered to invalid relative path {:?}.",
+                template, rendered
+            ),
+        ));
+    }
+    Ok(rendered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn placeholders(values: &[(&str, &str)]) -> HashMap<String, String> {
+        values
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn renders_placeholders() {
+        let values = placeholders(&[("term", "F24"), ("course", "CS101")]);
+        assert_eq!(
+            render_path_template("{term}/{course}", &values).unwrap(),
+            PathBuf::from("F24").join("CS101")
+        );
+        assert_eq!(
+            render_path_template("notes-{course}/{term}_archive", &values).unwrap(),
+            PathBuf::from("notes-CS101").join("F24_archive")
+        );
+        assert_eq!(
+            render_path_template("static", &values).unwrap(),
+            PathBuf::from("static")
+        );
+    }
+
+    #[test]
+    fn rejects_missing_and_unclosed_placeholders() {
+        let values = placeholders(&[("term", "F24")]);
+        for template in ["{term}/{course}", "{term}/{course"] {
+            let error = render_path_template(template, &values).unwrap_err();
+            assert!(
+                matches!(error.get_error_type(), ErrorType::FilepathError),
+                "{}",
+                template
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_paths_escaping_the_directory() {
+        let values = placeholders(&[("up", ".."), ("empty", "")]);
+        for template in ["{up}/secrets", "/etc/{empty}", "a/../../b"] {
+            assert!(
+                render_path_template(template, &values).is_err(),
+                "{}",
+                template
+            );
+        }
+        let values = placeholders(&[("term", "F24")]);
+        assert!(render_path_template("./{term}", &values).is_err());
+    }
+}
@@ -3,69 +3,499 @@ extern crate serde;
 extern crate serde_derive;
 extern crate serde_json;
 
+use chrono::{DateTime, Datelike, Local, NaiveDate};
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashSet,
+    collections::{BTreeSet, HashMap},
     ffi::OsStr,
+    fmt::Display,
+    fs,
     path::{Path, PathBuf},
+    str::FromStr,
     sync::{Arc, Mutex},
-    time::Duration,
+    time::{Duration, SystemTime},
 };
 
 use crate::{
+    config::WatchRoot,
     error::{ErrorType, FsmError},
-    filepath::FilepathManager,
+    filepath::{render_path_template, FilepathManager},
+    reader::{is_default, TimeInterval},
+    schedule::SweepSchedule,
 };
 
 /**
-Representation of supported file types for filters.
+File size used by the `min_size` / `max_size` filters. Can be written either as a number of bytes, or as a
+human readable size such as "10MB" or "1.5GiB".
+*/
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(try_from = "FileSizeRepresentation", into = "u64")]
+pub struct FileSize(u64);
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum FileSizeRepresentation {
+    Bytes(u64),
+    Formatted(String),
+}
+
+impl FileSize {
+    pub fn from_bytes(bytes: u64) -> Self {
+        Self(bytes)
+    }
+    pub fn bytes(&self) -> u64 {
+        self.0
+    }
+}
+
+impl FromStr for FileSize {
+    type Err = FsmError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let trimmed = s.trim();
+        let split_index = trimmed
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(trimmed.len());
+        let (amount, unit) = trimmed.split_at(split_index);
+        let invalid_size = || {
+            FsmError::new(
+                ErrorType::FilterError,
+                format!("Invalid file size {:?} provided.", s),
+            )
+        };
+        let amount: f64 = amount.parse().map_err(|_| invalid_size())?;
+        let multiplier: u64 = match unit.trim().to_lowercase().as_str() {
+            "" | "b" => 1,
+            "k" | "kb" => 1_000,
+            "m" | "mb" => 1_000_000,
+            "g" | "gb" => 1_000_000_000,
+            "t" | "tb" => 1_000_000_000_000,
+            "kib" => 1 << 10,
+            "mib" => 1 << 20,
+            "gib" => 1 << 30,
+            "tib" => 1 << 40,
+            _ => return Err(invalid_size()),
+        };
+        Ok(Self((amount * multiplier as f64).round() as u64))
+    }
+}
+
+impl TryFrom<FileSizeRepresentation> for FileSize {
+    type Error = FsmError;
+
+    fn try_from(value: FileSizeRepresentation) -> Result<Self, Self::Error> {
+        match value {
+            FileSizeRepresentation::Bytes(bytes) => Ok(Self(bytes)),
+            FileSizeRepresentation::Formatted(formatted) => formatted.parse(),
+        }
+    }
+}
+
+impl From<FileSize> for u64 {
+    fn from(size: FileSize) -> Self {
+        size.0
+    }
+}
+
+/**
+File timestamp used when checking the age of a file against a filter.
 */
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AgeTimestamp {
+    #[default]
+    Modified,
+    Created,
+    Accessed,
+}
 
+impl AgeTimestamp {
+    fn get_age(&self, file: &Path) -> Option<Duration> {
+        let metadata = fs::metadata(file).ok()?;
+        let timestamp = match self {
+            AgeTimestamp::Modified => metadata.modified(),
+            AgeTimestamp::Created => metadata.created(),
+            AgeTimestamp::Accessed => metadata.accessed(),
+        }
+        .ok()?;
+        // timestamps in the future are treated as brand new files
+        Some(
+            SystemTime::now()
+                .duration_since(timestamp)
+                .unwrap_or_default(),
+        )
+    }
+}
+
+/**
+Regex matched against file names, compiled once when the config is loaded. A pattern that does not
+compile is kept as written so that validation can report it, and never matches.
+*/
 #[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct FileFilter {
-    filename_pattern: Option<String>,
-    allowed_filetypes: Option<HashSet<String>>,
-    min_age: Option<Duration>, // todo - add filter here
-    max_age: Option<Duration>,
-    directory_key: String,
+#[serde(from = "String", into = "String")]
+pub struct FilenamePattern {
+    pattern: String,
+    regexp: Option<Regex>,
 }
 
-impl FileFilter {
+impl FilenamePattern {
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    pub fn get_regex(&self) -> Option<&Regex> {
+        self.regexp.as_ref()
+    }
+
+    /**
+    Whether the pattern matches every file name, e.g. `.*` or an empty pattern. Patterns that match the
+    empty string only at an anchor or word boundary, such as `^$`, do not.
+    */
+    pub fn matches_everything(&self) -> bool {
+        const ASSERTIONS: [&str; 6] = ["^", "$", "\\A", "\\z", "\\b", "\\B"];
+        self.regexp
+            .as_ref()
+            .is_some_and(|regexp| regexp.is_match(""))
+            && !ASSERTIONS
+                .iter()
+                .any(|assertion| self.pattern.contains(assertion))
+    }
+
     pub fn is_match(&self, file: &Path) -> bool {
-        let valid_filename = match &self.filename_pattern {
-            Some(pattern) => {
+        let filename = file.file_name().unwrap_or_default();
+        let filename = filename.to_str().unwrap_or_default();
+        self.regexp
+            .as_ref()
+            .is_some_and(|regexp| regexp.is_match(filename))
+    }
+
+    /**
+    Adds the named capture groups of the pattern for the given file to `captures`.
+    */
+    fn add_captures(&self, file: &Path, captures: &mut HashMap<String, String>) {
+        let regexp = match &self.regexp {
+            Some(res) => res,
+            None => return,
+        };
+        let filename = file.file_name().unwrap_or_default();
+        let filename = filename.to_str().unwrap_or_default();
+        if let Some(matched) = regexp.captures(filename) {
+            for name in regexp.capture_names().flatten() {
+                if let Some(value) = matched.name(name) {
+                    captures.insert(name.to_string(), value.as_str().to_string());
+                }
+            }
+        }
+    }
+}
+
+impl From<String> for FilenamePattern {
+    fn from(pattern: String) -> Self {
+        let regexp = Regex::new(&pattern).ok();
+        Self { pattern, regexp }
+    }
+}
+
+impl From<FilenamePattern> for String {
+    fn from(pattern: FilenamePattern) -> Self {
+        pattern.pattern
+    }
+}
+
+fn matches_filetypes(filetypes: &BTreeSet<String>, file: &Path) -> bool {
+    let extension = file.extension().and_then(OsStr::to_str).unwrap_or_default();
+    extension.is_empty() || filetypes.contains(extension)
+}
+
+fn matches_size(min_size: Option<FileSize>, max_size: Option<FileSize>, file: &Path) -> bool {
+    let size = match fs::metadata(file) {
+        Ok(res) => FileSize(res.len()),
+        Err(_) => return false,
+    };
+    !(min_size.is_some_and(|min_size| size < min_size)
+        || max_size.is_some_and(|max_size| size > max_size))
+}
+
+fn matches_age(
+    min_age: Option<&TimeInterval>,
+    max_age: Option<&TimeInterval>,
+    age_timestamp: AgeTimestamp,
+    file: &Path,
+) -> bool {
+    let age = match age_timestamp.get_age(file) {
+        Some(res) => res,
+        None => return false,
+    };
+    !(min_age.is_some_and(|min_age| age < min_age.clone().into())
+        || max_age.is_some_and(|max_age| age > max_age.clone().into()))
+}
+
+const DATE_PLACEHOLDERS: [&str; 5] = ["year", "month", "day", "week", "iso_year"];
+
+/**
+Source of the date used for the `{year}`, `{month}`, `{day}`, `{week}` and `{iso_year}` placeholders
+of a destination template. `{week}` is the ISO week, which belongs to `{iso_year}` rather than
+`{year}` around new year (2024-12-30 is in week 01 of 2025). `filename` takes a chrono format
+string (e.g. `"%Y-%m-%d"`) which is searched for anywhere in the file name.
+*/
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DateSource {
+    #[default]
+    Modified,
+    Now,
+    Filename(String),
+}
+
+impl DateSource {
+    fn get_date(&self, file: &Path) -> Result<NaiveDate, FsmError> {
+        match self {
+            DateSource::Modified => {
+                let modified = fs::metadata(file)?.modified()?;
+                Ok(DateTime::<Local>::from(modified).date_naive())
+            }
+            DateSource::Now => Ok(Local::now().date_naive()),
+            DateSource::Filename(format) => {
                 let filename = file.file_name().unwrap_or_default();
                 let filename = filename.to_str().unwrap_or_default();
-                let pattern_regex = Regex::new(format!(r#"{}"#, pattern).as_str());
-                match pattern_regex {
-                    Ok(regexp) => regexp.is_match(filename),
-                    Err(_) => false,
-                }
+                filename
+                    .char_indices()
+                    .find_map(|(index, _)| {
+                        NaiveDate::parse_and_remainder(&filename[index..], format).ok()
+                    })
+                    .map(|(date, _)| date)
+                    .ok_or_else(|| {
+                        FsmError::new(
+                            ErrorType::FilterError,
+                            format!(
+                                "Could not find a date matching {:?} in file name {:?}.",
+                                format, filename
+                            ),
+                        )
+                    })
             }
-            None => true,
+        }
+    }
+}
+
+/**
+What to do with a file once it matches a filter. Every action except `move` leaves the original (or
+a symlink to the new location) in the watched directory.
+*/
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum FileAction {
+    #[default]
+    Move,
+    Copy,
+    Hardlink,
+    Symlink,
+    MoveAndLeaveSymlink,
+}
+
+impl Display for FileAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let action = match self {
+            FileAction::Move => "move",
+            FileAction::Copy => "copy",
+            FileAction::Hardlink => "hardlink",
+            FileAction::Symlink => "symlink",
+            FileAction::MoveAndLeaveSymlink => "move-and-leave-symlink",
         };
-        if !valid_filename {
-            return false;
+        write!(f, "{}", action)
+    }
+}
+
+/**
+How to resolve a file that collides with an existing file at its mapped location. `rename` appends a
+numeric suffix (`report_0.pdf`, `report_1.pdf`, ...), while `keep-newer` and `keep-larger` overwrite
+the existing file only when the incoming one is newer or larger, and skip it otherwise. `conflicts`
+moves the incoming file to the conflicts folder of the managed path instead.
+*/
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum CollisionStrategy {
+    #[default]
+    Rename,
+    Skip,
+    Overwrite,
+    RenameTimestamp,
+    RenameHash,
+    KeepNewer,
+    KeepLarger,
+    Conflicts,
+}
+
+/**
+What to do with a moved file whose contents are identical to a file already in its managed
+directory. `keep` (the default) moves it like any other file, `skip` leaves it where it is and
+`duplicates` moves it to the duplicates folder of the managed path, where it is journaled like any
+other move. Only applies to the `move` action.
+*/
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DuplicateStrategy {
+    #[default]
+    Keep,
+    Skip,
+    Duplicates,
+}
+
+/**
+Nested condition tree for filters. Leaves are the same predicates that can be set directly on a
+`FileFilter`, and can be combined with `all`, `any` and `not` nodes, e.g.
+`{"all": [{"allowed_filetypes": ["pdf"]}, {"not": {"filename_pattern": "draft"}}]}`.
+Age predicates use the `age_timestamp` of the enclosing filter.
+*/
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterCondition {
+    All(Vec<FilterCondition>),
+    Any(Vec<FilterCondition>),
+    Not(Box<FilterCondition>),
+    FilenamePattern(FilenamePattern),
+    AllowedFiletypes(BTreeSet<String>),
+    MinAge(TimeInterval),
+    MaxAge(TimeInterval),
+    MinSize(FileSize),
+    MaxSize(FileSize),
+}
+
+impl FilterCondition {
+    pub fn is_match(&self, file: &Path, age_timestamp: AgeTimestamp) -> bool {
+        match self {
+            FilterCondition::All(conditions) => conditions
+                .iter()
+                .all(|condition| condition.is_match(file, age_timestamp)),
+            FilterCondition::Any(conditions) => conditions
+                .iter()
+                .any(|condition| condition.is_match(file, age_timestamp)),
+            FilterCondition::Not(condition) => !condition.is_match(file, age_timestamp),
+            FilterCondition::FilenamePattern(pattern) => pattern.is_match(file),
+            // unlike the top level `allowed_filetypes`, a leaf does not match files without an
+            // extension, so that `not` of it does
+            FilterCondition::AllowedFiletypes(filetypes) => file
+                .extension()
+                .and_then(OsStr::to_str)
+                .is_some_and(|extension| filetypes.contains(extension)),
+            FilterCondition::MinAge(min_age) => {
+                matches_age(Some(min_age), None, age_timestamp, file)
+            }
+            FilterCondition::MaxAge(max_age) => {
+                matches_age(None, Some(max_age), age_timestamp, file)
+            }
+            FilterCondition::MinSize(min_size) => matches_size(Some(*min_size), None, file),
+            FilterCondition::MaxSize(max_size) => matches_size(None, Some(*max_size), file),
         }
+    }
 
-        let allowed_filetype = match &self.allowed_filetypes {
-            Some(filetypes) => {
-                let extension = file.extension().and_then(OsStr::to_str).unwrap_or_default();
-                extension.is_empty() || filetypes.contains(extension)
+    /**
+    Adds the named capture groups of the `filename_pattern` leaves that matched the given file to
+    `captures`. Leaves under `not` never contribute, and neither do `any` branches that did not match.
+    */
+    fn add_captures(
+        &self,
+        file: &Path,
+        age_timestamp: AgeTimestamp,
+        captures: &mut HashMap<String, String>,
+    ) {
+        match self {
+            FilterCondition::All(conditions) | FilterCondition::Any(conditions) => {
+                for condition in conditions {
+                    if condition.is_match(file, age_timestamp) {
+                        condition.add_captures(file, age_timestamp, captures);
+                    }
+                }
             }
-            None => true,
-        };
-        if !allowed_filetype {
+            FilterCondition::FilenamePattern(pattern) => pattern.add_captures(file, captures),
+            _ => (),
+        }
+    }
+}
+
+/**
+Representation of supported file types for filters.
+*/
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FileFilter {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    filename_pattern: Option<FilenamePattern>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    allowed_filetypes: Option<BTreeSet<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    min_age: Option<TimeInterval>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_age: Option<TimeInterval>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    age_timestamp: AgeTimestamp,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    min_size: Option<FileSize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_size: Option<FileSize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    condition: Option<FilterCondition>,
+    directory_key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    destination_template: Option<String>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    date_source: DateSource,
+    #[serde(default, skip_serializing_if = "is_default")]
+    action: FileAction,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    on_collision: Option<CollisionStrategy>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    on_duplicate: Option<DuplicateStrategy>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    schedule: Option<SweepSchedule>,
+}
+
+impl FileFilter {
+    pub fn is_match(&self, file: &Path) -> bool {
+        if !self
+            .filename_pattern
+            .as_ref()
+            .is_none_or(|pattern| pattern.is_match(file))
+        {
+            return false;
+        }
+
+        if !self
+            .allowed_filetypes
+            .as_ref()
+            .is_none_or(|filetypes| matches_filetypes(filetypes, file))
+        {
             return false;
         }
 
-        // todo - functionality for min/max age
+        if (self.min_size.is_some() || self.max_size.is_some())
+            && !matches_size(self.min_size, self.max_size, file)
+        {
+            return false;
+        }
 
-        true
+        if (self.min_age.is_some() || self.max_age.is_some())
+            && !matches_age(
+                self.min_age.as_ref(),
+                self.max_age.as_ref(),
+                self.age_timestamp,
+                file,
+            )
+        {
+            return false;
+        }
+
+        match &self.condition {
+            Some(condition) => condition.is_match(file, self.age_timestamp),
+            None => true,
+        }
     }
     pub fn set_filename_pattern(&mut self, pattern: String) {
-        self.filename_pattern = Some(pattern);
+        self.filename_pattern = Some(pattern.into());
+    }
+    pub fn get_filename_pattern(&self) -> Option<&str> {
+        self.filename_pattern.as_ref().map(FilenamePattern::as_str)
     }
     pub fn add_allowed_filetype(&mut self, new_type: String) {
         match &mut self.allowed_filetypes {
@@ -73,7 +503,7 @@ impl FileFilter {
                 allowed_filetypes.insert(new_type);
             }
             None => {
-                let mut allowed_filetypes = HashSet::new();
+                let mut allowed_filetypes = BTreeSet::new();
                 allowed_filetypes.insert(new_type);
                 self.allowed_filetypes = Some(allowed_filetypes);
             }
@@ -81,13 +511,54 @@ impl FileFilter {
     }
 
     pub fn clear_allowed_filetypes(&mut self) {
-        match &mut self.allowed_filetypes {
-            Some(allowed_filetypes) => {
-                allowed_filetypes.clear();
-            }
-            None => {}
+        if let Some(allowed_filetypes) = &mut self.allowed_filetypes {
+            allowed_filetypes.clear();
         }
     }
+    pub fn set_min_age(&mut self, min_age: Option<TimeInterval>) {
+        self.min_age = min_age;
+    }
+
+    pub fn set_max_age(&mut self, max_age: Option<TimeInterval>) {
+        self.max_age = max_age;
+    }
+
+    pub fn set_age_timestamp(&mut self, age_timestamp: AgeTimestamp) {
+        self.age_timestamp = age_timestamp;
+    }
+
+    pub fn set_min_size(&mut self, min_size: Option<FileSize>) {
+        self.min_size = min_size;
+    }
+
+    pub fn set_max_size(&mut self, max_size: Option<FileSize>) {
+        self.max_size = max_size;
+    }
+
+    pub fn set_condition(&mut self, condition: Option<FilterCondition>) {
+        self.condition = condition;
+    }
+
+    pub fn get_condition(&self) -> Option<&FilterCondition> {
+        self.condition.as_ref()
+    }
+
+    /**
+    Whether the filter matches every file, leaving no file for the filters after it.
+    */
+    pub fn is_catch_all(&self) -> bool {
+        self.filename_pattern
+            .as_ref()
+            .is_none_or(FilenamePattern::matches_everything)
+            && self.allowed_filetypes.is_none()
+            && self.min_age.is_none()
+            && self.max_age.is_none()
+            && self.min_size.is_none()
+            && self.max_size.is_none()
+            && self.condition.is_none()
+            && self.schedule.is_none()
+    }
+
     pub fn set_directory_key(&mut self, key: String) {
         self.directory_key = key;
     }
@@ -95,27 +566,169 @@ impl FileFilter {
     pub fn get_directory_key(&self) -> &str {
         &self.directory_key
     }
+
+    pub fn set_destination_template(&mut self, template: Option<String>) {
+        self.destination_template = template;
+    }
+
+    pub fn get_destination_template(&self) -> Option<&str> {
+        self.destination_template.as_deref()
+    }
+
+    pub fn set_date_source(&mut self, date_source: DateSource) {
+        self.date_source = date_source;
+    }
+
+    pub fn set_action(&mut self, action: FileAction) {
+        self.action = action;
+    }
+
+    pub fn get_action(&self) -> FileAction {
+        self.action
+    }
+
+    pub fn set_collision_strategy(&mut self, on_collision: Option<CollisionStrategy>) {
+        self.on_collision = on_collision;
+    }
+
+    /**
+    Collision strategy of this filter, if it overrides the global one.
+    */
+    pub fn get_collision_strategy(&self) -> Option<CollisionStrategy> {
+        self.on_collision
+    }
+
+    pub fn set_duplicate_strategy(&mut self, on_duplicate: Option<DuplicateStrategy>) {
+        self.on_duplicate = on_duplicate;
+    }
+
+    /**
+    Duplicate strategy of this filter, if it overrides the global one.
+    */
+    pub fn get_duplicate_strategy(&self) -> Option<DuplicateStrategy> {
+        self.on_duplicate
+    }
+
+    pub fn set_schedule(&mut self, schedule: Option<SweepSchedule>) {
+        self.schedule = schedule;
+    }
+
+    /**
+    Schedule of this filter, if it only applies during scheduled sweeps.
+    */
+    pub fn get_schedule(&self) -> Option<&SweepSchedule> {
+        self.schedule.as_ref()
+    }
+
+    /**
+    Whether the filter applies to files found during the given scheduled sweep (or outside of one).
+    Filters without a schedule always apply.
+    */
+    pub fn is_active(&self, schedule: Option<&SweepSchedule>) -> bool {
+        self.schedule.is_none() || self.schedule.as_ref() == schedule
+    }
+
+    /**
+    Returns the placeholders used to render the destination template of this filter for the given
+    file. Named capture groups of `filename_pattern` take precedence over the date placeholders,
+    which are only resolved when the template uses them.
+    */
+    pub fn get_placeholders(&self, file: &Path) -> Result<HashMap<String, String>, FsmError> {
+        let mut placeholders = HashMap::new();
+        let template = self.destination_template.as_deref().unwrap_or_default();
+        if DATE_PLACEHOLDERS
+            .iter()
+            .any(|placeholder| template.contains(&format!("{{{}}}", placeholder)))
+        {
+            let date = self.date_source.get_date(file)?;
+            placeholders.insert("year".to_string(), format!("{:04}", date.year()));
+            placeholders.insert("month".to_string(), format!("{:02}", date.month()));
+            placeholders.insert("day".to_string(), format!("{:02}", date.day()));
+            placeholders.insert("week".to_string(), format!("{:02}", date.iso_week().week()));
+            placeholders.insert(
+                "iso_year".to_string(),
+                format!("{:04}", date.iso_week().year()),
+            );
+        }
+        placeholders.extend(self.get_captures(file));
+        Ok(placeholders)
+    }
+
+    /**
+    Returns the named capture groups of `filename_pattern` and of the matching `filename_pattern`
+    leaves of `condition` for the given file. The top level pattern wins when both capture a name.
+    */
+    pub fn get_captures(&self, file: &Path) -> HashMap<String, String> {
+        let mut captures = HashMap::new();
+        if let Some(condition) = &self.condition {
+            condition.add_captures(file, self.age_timestamp, &mut captures);
+        }
+        if let Some(pattern) = &self.filename_pattern {
+            pattern.add_captures(file, &mut captures);
+        }
+        captures
+    }
 }
 
 impl Default for FileFilter {
     fn default() -> Self {
         Self {
             filename_pattern: None,
-            allowed_filetypes: Some(HashSet::new()),
+            allowed_filetypes: Some(BTreeSet::new()),
             min_age: None,
             max_age: None,
+            age_timestamp: AgeTimestamp::default(),
+            min_size: None,
+            max_size: None,
+            condition: None,
             directory_key: "".to_string(),
+            destination_template: None,
+            date_source: DateSource::default(),
+            action: FileAction::default(),
+            on_collision: None,
+            on_duplicate: None,
+            schedule: None,
         }
     }
 }
 
+/**
+Location a file maps to, along with the filter that matched it.
+*/
+#[derive(Debug)]
+pub struct MappedLocation<'a> {
+    pub location: PathBuf,
+    pub filter_index: usize,
+    pub filter: &'a FileFilter,
+}
+
 pub struct FilterManager {
     filters: Vec<FileFilter>,
+    // filters of watch roots that have their own, keyed by the watched path
+    root_filters: HashMap<PathBuf, Vec<FileFilter>>,
 }
 
 impl FilterManager {
     pub fn new(filters: Vec<FileFilter>) -> Self {
-        Self { filters }
+        Self {
+            filters,
+            root_filters: HashMap::new(),
+        }
+    }
+    /**
+    Returns every filter, including the ones specific to a watch root.
+    */
+    pub fn get_all_filters(&self) -> impl Iterator<Item = &FileFilter> {
+        self.filters
+            .iter()
+            .chain(self.root_filters.values().flatten())
+    }
+    pub fn set_root_filters<P>(&mut self, watch_path: P, filters: Vec<FileFilter>)
+    where
+        P: AsRef<Path>,
+    {
+        self.root_filters
+            .insert(watch_path.as_ref().to_path_buf(), filters);
     }
     pub fn get_mapped_location<P>(
         &self,
@@ -125,7 +738,59 @@ impl FilterManager {
     where
         P: AsRef<Path>,
     {
-        let path_ref = file_to_move.as_ref();
+        self.get_mapping(file_to_move, None, filepath_manager)
+            .map(|mapping| mapping.location)
+    }
+    pub fn get_mapping<P>(
+        &self,
+        file_to_move: &P,
+        schedule: Option<&SweepSchedule>,
+        filepath_manager: Arc<Mutex<FilepathManager>>,
+    ) -> Result<MappedLocation<'_>, FsmError>
+    where
+        P: AsRef<Path>,
+    {
+        self.map_with_filters(
+            file_to_move.as_ref(),
+            &self.filters,
+            None,
+            schedule,
+            filepath_manager,
+        )
+    }
+    /**
+    Maps a file using the filters and managed directory of the watch root it was found in.
+    */
+    pub fn get_mapping_in_root<P>(
+        &self,
+        file_to_move: &P,
+        watch_root: &WatchRoot,
+        schedule: Option<&SweepSchedule>,
+        filepath_manager: Arc<Mutex<FilepathManager>>,
+    ) -> Result<MappedLocation<'_>, FsmError>
+    where
+        P: AsRef<Path>,
+    {
+        let filters = self
+            .root_filters
+            .get(watch_root.get_path())
+            .unwrap_or(&self.filters);
+        self.map_with_filters(
+            file_to_move.as_ref(),
+            filters,
+            Some(watch_root.get_managed_path()),
+            schedule,
+            filepath_manager,
+        )
+    }
+    fn map_with_filters<'a>(
+        &self,
+        path_ref: &Path,
+        filters: &'a [FileFilter],
+        managed_root: Option<&Path>,
+        schedule: Option<&SweepSchedule>,
+        filepath_manager: Arc<Mutex<FilepathManager>>,
+    ) -> Result<MappedLocation<'a>, FsmError> {
         if !path_ref.exists() {
             return Err(FsmError::new(
                 ErrorType::FilterError,
@@ -135,11 +800,26 @@ impl FilterManager {
                 ),
             ));
         }
-        let matching_filter = match self.filters.iter().find(|f| f.is_match(path_ref)) {
+        let (filter_index, matching_filter) = match filters
+            .iter()
+            .enumerate()
+            .find(|(_, f)| f.is_match(path_ref))
+        {
+            // the file waits for its filter's schedule rather than falling through to later filters
+            Some((filter_index, f)) if !f.is_active(schedule) => {
+                return Err(FsmError::new(
+                    ErrorType::OffScheduleError,
+                    format!(
+                        "The file {} matches filter {} outside of its schedule.",
+                        path_ref.to_str().unwrap_or_default(),
+                        filter_index
+                    ),
+                ))
+            }
             Some(res) => res,
             None => {
                 return Err(FsmError::new(
-                    ErrorType::FilterError,
+                    ErrorType::NoMatchError,
                     format!(
                         "The file {} does not match any filters.",
                         path_ref.to_str().unwrap_or_default()
@@ -153,7 +833,12 @@ impl FilterManager {
             Ok(res) => res,
         };
 
-        let path_mapping = match filepath_manager.get(matching_filter.get_directory_key()) {
+        let directory_key = matching_filter.get_directory_key();
+        let path_mapping = match managed_root {
+            Some(managed_root) => filepath_manager.get_in_root(managed_root, directory_key),
+            None => filepath_manager.get(directory_key),
+        };
+        let path_mapping = match path_mapping {
             Some(res) => res,
             None => {
                 return Err(FsmError::new(
@@ -166,15 +851,360 @@ impl FilterManager {
             }
         };
 
+        let path_mapping = match matching_filter.get_destination_template() {
+            Some(template) => path_mapping.join(render_path_template(
+                template,
+                &matching_filter.get_placeholders(path_ref)?,
+            )?),
+            None => path_mapping.to_path_buf(),
+        };
+
         let file_name = match path_ref.file_name() {
             Some(res) => res,
             None => {
                 return Err(FsmError::new(
                     ErrorType::FilterError,
-                    format!("Could not obtain file name."),
+                    "Could not obtain file name.".to_string(),
                 ))
             }
         };
-        Ok(path_mapping.join(file_name))
+        Ok(MappedLocation {
+            location: path_mapping.join(file_name),
+            filter_index,
+            filter: matching_filter,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn parses_file_sizes() {
+        let cases = [
+            ("512", 512),
+            ("512b", 512),
+            ("10KB", 10_000),
+            ("10 kb", 10_000),
+            ("1.5MB", 1_500_000),
+            ("2g", 2_000_000_000),
+            ("1TB", 1_000_000_000_000),
+            ("1KiB", 1024),
+            ("1.5GiB", 3 << 29),
+            (" 4 MiB ", 4 << 20),
+        ];
+        for (formatted, bytes) in cases {
+            let size: FileSize = formatted.parse().unwrap();
+            assert_eq!(size.bytes(), bytes, "{}", formatted);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_file_sizes() {
+        for formatted in ["", "MB", "10 XB", "1.2.3KB", "-5"] {
+            let error = formatted.parse::<FileSize>().unwrap_err();
+            assert!(
+                matches!(error.get_error_type(), ErrorType::FilterError),
+                "{}",
+                formatted
+            );
+        }
+    }
+
+    #[test]
+    fn deserializes_file_sizes() {
+        let size: FileSize = serde_json::from_str("2048").unwrap();
+        assert_eq!(size, FileSize::from_bytes(2048));
+        let size: FileSize = serde_json::from_str("\"2KiB\"").unwrap();
+        assert_eq!(size, FileSize::from_bytes(2048));
+        assert_eq!(serde_json::to_string(&size).unwrap(), "2048");
+        assert!(serde_json::from_str::<FileSize>("\"two\"").is_err());
+    }
+
+    fn condition(json: &str) -> FilterCondition {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn is_match(condition: &FilterCondition, file: &str) -> bool {
+        condition.is_match(Path::new(file), AgeTimestamp::default())
+    }
+
+    #[test]
+    fn matches_condition_leaves() {
+        let pattern = condition(r#"{"filename_pattern": "^report"}"#);
+        assert!(is_match(&pattern, "/tmp/report_2024.pdf"));
+        assert!(!is_match(&pattern, "/tmp/annual_report.pdf"));
+
+        let filetypes = condition(r#"{"allowed_filetypes": ["pdf", "docx"]}"#);
+        assert!(is_match(&filetypes, "/tmp/report.pdf"));
+        assert!(!is_match(&filetypes, "/tmp/report.txt"));
+        assert!(!is_match(&filetypes, "/tmp/Makefile"));
+
+        let invalid = condition(r#"{"filename_pattern": "("}"#);
+        assert!(!is_match(&invalid, "/tmp/("));
+    }
+
+    #[test]
+    fn combines_conditions() {
+        let tree = condition(
+            r#"{"all": [
+                {"allowed_filetypes": ["pdf"]},
+                {"not": {"filename_pattern": "draft"}},
+                {"any": [{"filename_pattern": "^invoice"}, {"filename_pattern": "^receipt"}]}
+            ]}"#,
+        );
+        assert!(is_match(&tree, "/tmp/invoice_01.pdf"));
+        assert!(is_match(&tree, "/tmp/receipt_01.pdf"));
+        assert!(!is_match(&tree, "/tmp/invoice_01_draft.pdf"));
+        assert!(!is_match(&tree, "/tmp/invoice_01.txt"));
+        assert!(!is_match(&tree, "/tmp/letter.pdf"));
+
+        assert!(is_match(&condition(r#"{"all": []}"#), "/tmp/a.txt"));
+        assert!(!is_match(&condition(r#"{"any": []}"#), "/tmp/a.txt"));
+    }
+
+    #[test]
+    fn negates_filetypes_for_files_without_extension() {
+        let not_pdf = condition(r#"{"not": {"allowed_filetypes": ["pdf"]}}"#);
+        assert!(is_match(&not_pdf, "/tmp/README"));
+        assert!(is_match(&not_pdf, "/tmp/notes.txt"));
+        assert!(!is_match(&not_pdf, "/tmp/report.pdf"));
+    }
+
+    #[test]
+    fn matches_size_conditions() {
+        let file = env::temp_dir().join(format!("fsm_filter_size_{}.bin", std::process::id()));
+        fs::write(&file, vec![0; 2048]).unwrap();
+        let file_path = file.to_str().unwrap();
+        assert!(is_match(&condition(r#"{"min_size": "1KiB"}"#), file_path));
+        assert!(!is_match(&condition(r#"{"min_size": "4KiB"}"#), file_path));
+        assert!(is_match(&condition(r#"{"max_size": 2048}"#), file_path));
+        assert!(!is_match(&condition(r#"{"max_size": "1KB"}"#), file_path));
+        assert!(!is_match(
+            &condition(r#"{"min_size": 0}"#),
+            "/tmp/fsm_missing_file"
+        ));
+        fs::remove_file(&file).unwrap();
+    }
+
+    fn date_filter(template: &str) -> FileFilter {
+        let mut filter = FileFilter::default();
+        filter.set_destination_template(Some(template.to_string()));
+        filter.set_date_source(DateSource::Filename("%Y-%m-%d".to_string()));
+        filter
+    }
+
+    #[test]
+    fn resolves_date_placeholders() {
+        let filter = date_filter("{year}/{month}/{day}");
+        let placeholders = filter
+            .get_placeholders(Path::new("/tmp/scan_2024-03-07_final.pdf"))
+            .unwrap();
+        assert_eq!(placeholders["year"], "2024");
+        assert_eq!(placeholders["month"], "03");
+        assert_eq!(placeholders["day"], "07");
+        assert_eq!(placeholders["week"], "10");
+        assert_eq!(placeholders["iso_year"], "2024");
+    }
+
+    #[test]
+    fn uses_iso_year_for_weeks() {
+        let filter = date_filter("{iso_year}/{week}");
+        let placeholders = filter
+            .get_placeholders(Path::new("/tmp/2024-12-30.txt"))
+            .unwrap();
+        assert_eq!(placeholders["year"], "2024");
+        assert_eq!(placeholders["iso_year"], "2025");
+        assert_eq!(placeholders["week"], "01");
+
+        let placeholders = filter
+            .get_placeholders(Path::new("/tmp/2021-01-03.txt"))
+            .unwrap();
+        assert_eq!(placeholders["year"], "2021");
+        assert_eq!(placeholders["iso_year"], "2020");
+        assert_eq!(placeholders["week"], "53");
+    }
+
+    #[test]
+    fn only_resolves_dates_when_used() {
+        let filter = date_filter("archive/{name}");
+        let placeholders = filter
+            .get_placeholders(Path::new("/tmp/undated.txt"))
+            .unwrap();
+        assert!(placeholders.is_empty());
+
+        let filter = date_filter("{year}");
+        let error = filter
+            .get_placeholders(Path::new("/tmp/undated.txt"))
+            .unwrap_err();
+        assert!(matches!(error.get_error_type(), ErrorType::FilterError));
+    }
+
+    #[test]
+    fn collects_captures_from_matching_leaves() {
+        let mut filter = FileFilter::default();
+        filter.set_filename_pattern(r"^(?P<course>[A-Z]+\d+)_".to_string());
+        filter.set_condition(Some(condition(
+            r#"{"all": [
+                {"filename_pattern": "_(?P<term>[FWS]\\d{2})"},
+                {"any": [
+                    {"filename_pattern": "(?P<kind>lecture)"},
+                    {"filename_pattern": "(?P<kind>assignment)"}
+                ]},
+                {"not": {"filename_pattern": "(?P<draft>draft)"}},
+                {"filename_pattern": "(?P<course>[a-z]+)\\."}
+            ]}"#,
+        )));
+        let captures = filter.get_captures(Path::new("/tmp/CS101_F24_assignment.pdf"));
+        let expected: HashMap<String, String> =
+            [("course", "CS101"), ("term", "F24"), ("kind", "assignment")]
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect();
+        assert_eq!(captures, expected);
+    }
+
+    #[test]
+    fn detects_catch_all_patterns() {
+        let with_pattern = |pattern: &str| {
+            let mut filter: FileFilter = serde_json::from_str(r#"{"directory_key": "a"}"#).unwrap();
+            assert!(filter.is_catch_all());
+            filter.set_filename_pattern(pattern.to_string());
+            filter
+        };
+        for pattern in ["", ".*", "(?i).*", "x*"] {
+            assert!(with_pattern(pattern).is_catch_all(), "{}", pattern);
+        }
+        for pattern in ["^$", "^.*\\.pdf$", "report", ".+", "\\b.*", "("] {
+            assert!(!with_pattern(pattern).is_catch_all(), "{}", pattern);
+        }
+        let mut filter = with_pattern(".*");
+        filter.set_condition(Some(condition(r#"{"filename_pattern": "^report"}"#)));
+        assert!(!filter.is_catch_all());
+    }
+
+    #[test]
+    fn leaves_files_of_inactive_filters_in_place() {
+        let directory = env::temp_dir().join(format!("fsm_filter_schedule_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        let file = directory.join("report.pdf");
+        fs::write(&file, "").unwrap();
+        let structure =
+            serde_json::from_str(r#"{"archive": "archive", "other": "other"}"#).unwrap();
+        let filepath_manager = Arc::new(Mutex::new(
+            FilepathManager::new(&directory, &structure, true).unwrap(),
+        ));
+        let filters = serde_json::from_str(
+            r#"[
+                {"allowed_filetypes": ["pdf"], "directory_key": "archive", "schedule": "0 2 * * *"},
+                {"directory_key": "other", "filename_pattern": ".*"}
+            ]"#,
+        )
+        .unwrap();
+        let filter_manager = FilterManager::new(filters);
+        let nightly: SweepSchedule = "0 2 * * *".parse().unwrap();
+        let hourly: SweepSchedule = "0 * * * *".parse().unwrap();
+
+        let mapping = filter_manager
+            .get_mapping(&file, Some(&nightly), filepath_manager.clone())
+            .unwrap();
+        assert_eq!(mapping.filter_index, 0);
+        for schedule in [None, Some(&hourly)] {
+            let error = filter_manager
+                .get_mapping(&file, schedule, filepath_manager.clone())
+                .unwrap_err();
+            assert!(matches!(
+                error.get_error_type(),
+                ErrorType::OffScheduleError
+            ));
+        }
+        // files the scheduled filter does not match still reach the catch-all
+        let other = directory.join("notes.txt");
+        fs::write(&other, "").unwrap();
+        let mapping = filter_manager
+            .get_mapping(&other, None, filepath_manager)
+            .unwrap();
+        assert_eq!(mapping.filter_index, 1);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    fn interval(seconds: u64) -> TimeInterval {
+        TimeInterval::new(seconds, 0, 0)
+    }
+
+    #[test]
+    fn matches_file_ages() {
+        let file = env::temp_dir().join(format!("fsm_filter_age_{}.txt", std::process::id()));
+        fs::write(&file, "").unwrap();
+        let now = SystemTime::now();
+        let two_hours = 2 * 60 * 60;
+        fs::File::options()
+            .write(true)
+            .open(&file)
+            .unwrap()
+            .set_times(
+                fs::FileTimes::new()
+                    .set_modified(now - Duration::from_secs(two_hours))
+                    .set_accessed(now - Duration::from_secs(60)),
+            )
+            .unwrap();
+        let modified = AgeTimestamp::Modified;
+        let min = |seconds| Some(interval(seconds));
+        let max = |seconds| Some(interval(seconds));
+
+        // a file exactly as old as the bounds is old enough but already too old
+        assert!(matches_age(min(two_hours).as_ref(), None, modified, &file));
+        assert!(!matches_age(None, max(two_hours).as_ref(), modified, &file));
+        assert!(!matches_age(
+            min(two_hours + 60).as_ref(),
+            None,
+            modified,
+            &file
+        ));
+        assert!(matches_age(
+            None,
+            max(two_hours + 60).as_ref(),
+            modified,
+            &file
+        ));
+        assert!(matches_age(
+            min(two_hours - 60).as_ref(),
+            max(two_hours + 60).as_ref(),
+            modified,
+            &file
+        ));
+        assert!(matches_age(None, None, modified, &file));
+
+        let accessed = AgeTimestamp::Accessed;
+        assert!(matches_age(
+            min(30).as_ref(),
+            max(120).as_ref(),
+            accessed,
+            &file
+        ));
+        assert!(!matches_age(min(120).as_ref(), None, accessed, &file));
+
+        // the creation time cannot be set, the file was just created
+        let created = AgeTimestamp::Created;
+        let has_created = fs::metadata(&file).unwrap().created().is_ok();
+        assert_eq!(
+            matches_age(None, max(60).as_ref(), created, &file),
+            has_created
+        );
+        assert!(!matches_age(min(60).as_ref(), None, created, &file));
+
+        let min_age = condition(r#"{"min_age": {"hours": 1}}"#);
+        let max_age = condition(r#"{"max_age": {"minutes": 5}}"#);
+        assert!(min_age.is_match(&file, modified));
+        assert!(!max_age.is_match(&file, modified));
+        assert!(!min_age.is_match(&file, accessed));
+        assert!(max_age.is_match(&file, accessed));
+
+        fs::remove_file(&file).unwrap();
+        assert!(!matches_age(None, None, modified, &file));
+        assert!(!max_age.is_match(&file, accessed));
     }
 }
@@ -0,0 +1,300 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::error::{ErrorType, FsmError};
+
+const HASH_BUFFER_SIZE: usize = 64 * 1024;
+
+/**
+Returns the hex encoded SHA-256 hash of the contents of a file.
+*/
+pub fn hash_file(path: &Path) -> io::Result<String> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; HASH_BUFFER_SIZE];
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+/**
+Index of content hashes for the files in each managed directory, persisted between runs so that
+duplicates can be found even when their names differ.
+*/
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct HashIndex {
+    #[serde(skip)]
+    index_path: PathBuf,
+    hashes: HashMap<String, HashMap<String, PathBuf>>,
+}
+
+impl HashIndex {
+    pub fn open<P>(index_path: P) -> Result<Self, FsmError>
+    where
+        P: AsRef<Path>,
+    {
+        let mut index = if index_path.as_ref().exists() {
+            let reader = BufReader::new(File::open(index_path.as_ref())?);
+            serde_json::from_reader(reader)?
+        } else {
+            Self::default()
+        };
+        index.index_path = index_path.as_ref().to_path_buf();
+        Ok(index)
+    }
+
+    pub fn get_index_path(&self) -> &PathBuf {
+        &self.index_path
+    }
+
+    pub fn is_indexed(&self, directory_key: &str) -> bool {
+        self.hashes.contains_key(directory_key)
+    }
+
+    /**
+    Adds the hashes of a newly indexed directory, unless it was indexed in the meantime.
+    */
+    pub fn add_directory(
+        &mut self,
+        directory_key: &str,
+        hashes: HashMap<String, PathBuf>,
+    ) -> Result<(), FsmError> {
+        if self.is_indexed(directory_key) {
+            return Ok(());
+        }
+        self.hashes.insert(directory_key.to_string(), hashes);
+        self.save()
+    }
+
+    pub fn get(&self, directory_key: &str, hash: &str) -> Option<&PathBuf> {
+        self.hashes.get(directory_key)?.get(hash)
+    }
+
+    /**
+    Drops a stale entry, unless it was replaced by another path in the meantime.
+    */
+    pub fn remove(&mut self, directory_key: &str, hash: &str, path: &Path) -> Result<(), FsmError> {
+        let hashes = match self.hashes.get_mut(directory_key) {
+            Some(res) => res,
+            None => return Ok(()),
+        };
+        if hashes.get(hash).is_some_and(|indexed| indexed == path) {
+            hashes.remove(hash);
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    pub fn insert<P>(&mut self, directory_key: &str, hash: &str, path: P) -> Result<(), FsmError>
+    where
+        P: AsRef<Path>,
+    {
+        self.hashes
+            .entry(directory_key.to_string())
+            .or_default()
+            .insert(hash.to_string(), path.as_ref().to_path_buf());
+        self.save()
+    }
+
+    fn save(&self) -> Result<(), FsmError> {
+        if let Some(parent) = self.index_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut temp_name = self.index_path.as_os_str().to_owned();
+        temp_name.push(".tmp");
+        let temp_path = PathBuf::from(temp_name);
+        let mut writer = BufWriter::new(File::create(&temp_path)?);
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush()?;
+        drop(writer);
+        fs::rename(&temp_path, &self.index_path)?;
+        Ok(())
+    }
+}
+
+fn index_directory(
+    directory: &Path,
+    hashes: &mut HashMap<String, PathBuf>,
+) -> Result<(), FsmError> {
+    if !directory.is_dir() {
+        return Ok(());
+    }
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            index_directory(&path, hashes)?;
+        } else if path.is_file() {
+            hashes.insert(hash_file(&path)?, path);
+        }
+    }
+    Ok(())
+}
+
+fn lock_index(hash_index: &Mutex<HashIndex>) -> Result<MutexGuard<'_, HashIndex>, FsmError> {
+    match hash_index.lock() {
+        Err(e) => Err(FsmError::new(ErrorType::ApplicationError, e.to_string())),
+        Ok(res) => Ok(res),
+    }
+}
+
+/**
+Finds an existing file in the managed directory with the given hash. The directory is indexed (and
+the index saved) the first time its key is looked up, and stale entries are dropped as they are
+found. Unless `update` is set (e.g. during a dry run), the index is left untouched and directories
+that are not indexed yet are hashed for this lookup only. The index is only locked to read and update
+entries, never while files are being hashed.
+*/
+pub fn find_duplicate(
+    hash_index: &Mutex<HashIndex>,
+    directory_key: &str,
+    directory: &Path,
+    hash: &str,
+    update: bool,
+) -> Result<Option<PathBuf>, FsmError> {
+    let candidate = if lock_index(hash_index)?.is_indexed(directory_key) {
+        lock_index(hash_index)?.get(directory_key, hash).cloned()
+    } else {
+        let mut hashes = HashMap::new();
+        index_directory(directory, &mut hashes)?;
+        let candidate = hashes.get(hash).cloned();
+        if update {
+            lock_index(hash_index)?.add_directory(directory_key, hashes)?;
+        }
+        candidate
+    };
+    let candidate = match candidate {
+        Some(res) => res,
+        None => return Ok(None),
+    };
+    if candidate.is_file() && hash_file(&candidate)? == hash {
+        return Ok(Some(candidate));
+    }
+    if update {
+        lock_index(hash_index)?.remove(directory_key, hash, &candidate)?;
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn test_directory(name: &str) -> PathBuf {
+        let directory = env::temp_dir().join(format!("fsm_hash_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(directory.join("managed").join("nested")).unwrap();
+        directory
+    }
+
+    #[test]
+    fn indexes_directories_and_finds_duplicates() {
+        let directory = test_directory("find");
+        let managed = directory.join("managed");
+        fs::write(managed.join("report.pdf"), "report").unwrap();
+        fs::write(managed.join("nested").join("notes.txt"), "notes").unwrap();
+        let index_path = directory.join("fsm_hash_index.json");
+        let hash_index = Mutex::new(HashIndex::open(&index_path).unwrap());
+
+        let hash = hash_file(&managed.join("nested").join("notes.txt")).unwrap();
+        assert_eq!(
+            find_duplicate(&hash_index, "a", &managed, &hash, true).unwrap(),
+            Some(managed.join("nested").join("notes.txt"))
+        );
+        let index = hash_index.lock().unwrap();
+        assert!(index.is_indexed("a"));
+        assert_eq!(
+            index.get("a", &hash_file(&managed.join("report.pdf")).unwrap()),
+            Some(&managed.join("report.pdf"))
+        );
+        assert_eq!(index.get("a", "0000"), None);
+        drop(index);
+        assert_eq!(
+            find_duplicate(&hash_index, "a", &managed, "0000", true).unwrap(),
+            None
+        );
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn drops_stale_entries() {
+        let directory = test_directory("stale");
+        let managed = directory.join("managed");
+        let report = managed.join("report.pdf");
+        fs::write(&report, "report").unwrap();
+        let hash = hash_file(&report).unwrap();
+        let hash_index = Mutex::new(HashIndex::open(directory.join("index.json")).unwrap());
+        assert!(find_duplicate(&hash_index, "a", &managed, &hash, true)
+            .unwrap()
+            .is_some());
+
+        fs::write(&report, "changed").unwrap();
+        assert_eq!(
+            find_duplicate(&hash_index, "a", &managed, &hash, true).unwrap(),
+            None
+        );
+        assert_eq!(hash_index.lock().unwrap().get("a", &hash), None);
+
+        fs::write(&report, "report").unwrap();
+        hash_index
+            .lock()
+            .unwrap()
+            .insert("a", &hash, &report)
+            .unwrap();
+        fs::remove_file(&report).unwrap();
+        assert_eq!(
+            find_duplicate(&hash_index, "a", &managed, &hash, true).unwrap(),
+            None
+        );
+        assert_eq!(hash_index.lock().unwrap().get("a", &hash), None);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn persists_the_index() {
+        let directory = test_directory("persist");
+        let managed = directory.join("managed");
+        let report = managed.join("report.pdf");
+        fs::write(&report, "report").unwrap();
+        let hash = hash_file(&report).unwrap();
+        let index_path = directory.join("fsm_hash_index.json");
+
+        let hash_index = Mutex::new(HashIndex::open(&index_path).unwrap());
+        find_duplicate(&hash_index, "a", &managed, &hash, false).unwrap();
+        assert!(!index_path.exists());
+        assert!(!hash_index.lock().unwrap().is_indexed("a"));
+
+        find_duplicate(&hash_index, "a", &managed, &hash, true).unwrap();
+        let moved = managed.join("moved.txt");
+        fs::write(&moved, "moved").unwrap();
+        let moved_hash = hash_file(&moved).unwrap();
+        hash_index
+            .lock()
+            .unwrap()
+            .insert("b", &moved_hash, &moved)
+            .unwrap();
+
+        let reopened = HashIndex::open(&index_path).unwrap();
+        assert_eq!(reopened.get_index_path(), &index_path);
+        assert_eq!(reopened.get("a", &hash), Some(&report));
+        assert_eq!(reopened.get("b", &moved_hash), Some(&moved));
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,587 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{ErrorType, FsmError},
+    filter::FileAction,
+    transfer::rename_file,
+};
+
+/**
+A single move recorded in the journal. Entries created by an undo reference the entry they reversed
+through `undoes`.
+*/
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JournalEntry {
+    pub id: u64,
+    pub timestamp: DateTime<Local>,
+    pub original_path: PathBuf,
+    pub final_path: PathBuf,
+    #[serde(default)]
+    pub action: FileAction,
+    pub filter_index: usize,
+    pub directory_key: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub undoes: Option<u64>,
+}
+
+/**
+Append-only journal of the moves performed by fsm, stored as one JSON entry per line. Every write
+happens under a lock on the journal file and first reads the entries appended by other fsm processes
+(e.g. `fsm undo` while fsm runs), so that ids never collide.
+*/
+#[derive(Debug)]
+pub struct MoveJournal {
+    journal_path: PathBuf,
+    next_id: u64,
+    // bytes of the journal already read
+    read_length: u64,
+    // original paths left in place by an active copy, link or move-and-leave-symlink
+    mirrored: HashSet<PathBuf>,
+    // paths files were put back at by an undo, along with when
+    restored: HashMap<PathBuf, DateTime<Local>>,
+}
+
+fn parse_entries(reader: impl BufRead, journal_path: &Path) -> Result<Vec<JournalEntry>, FsmError> {
+    let mut entries = vec![];
+    for (line_number, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<JournalEntry>(&line) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => {
+                return Err(FsmError::new(
+                    ErrorType::JournalError,
+                    format!(
+                        "Invalid entry on line {} of {:?}: {}",
+                        line_number + 1,
+                        journal_path,
+                        e
+                    ),
+                ))
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/**
+Moves that have not been undone yet, oldest first.
+*/
+fn get_active(entries: Vec<JournalEntry>) -> Vec<JournalEntry> {
+    let undone: HashSet<u64> = entries.iter().filter_map(|entry| entry.undoes).collect();
+    entries
+        .into_iter()
+        .filter(|entry| entry.undoes.is_none() && !undone.contains(&entry.id))
+        .collect()
+}
+
+/**
+Puts a moved file back, or removes the copy or link that was created. Returns false if the move can
+no longer be reversed.
+*/
+fn reverse_move(entry: &JournalEntry) -> Result<bool, FsmError> {
+    if fs::symlink_metadata(&entry.final_path).is_err() {
+        warn!(
+            "Cannot undo {} of {:?}, {:?} no longer exists.",
+            entry.action, entry.original_path, entry.final_path
+        );
+        return Ok(false);
+    }
+    match entry.action {
+        FileAction::Move | FileAction::MoveAndLeaveSymlink => {
+            let left_symlink = entry.action == FileAction::MoveAndLeaveSymlink
+                && fs::symlink_metadata(&entry.original_path)
+                    .is_ok_and(|metadata| metadata.file_type().is_symlink());
+            if left_symlink {
+                fs::remove_file(&entry.original_path)?;
+            }
+            if fs::symlink_metadata(&entry.original_path).is_ok() {
+                warn!(
+                    "Cannot undo {} of {:?}, the original path is taken.",
+                    entry.action, entry.original_path
+                );
+                return Ok(false);
+            }
+            if let Some(parent) = entry.original_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            rename_file(&entry.final_path, &entry.original_path)?;
+        }
+        FileAction::Copy | FileAction::Hardlink | FileAction::Symlink => {
+            fs::remove_file(&entry.final_path)?;
+        }
+    }
+    Ok(true)
+}
+
+impl MoveJournal {
+    pub fn open<P>(journal_path: P) -> Result<Self, FsmError>
+    where
+        P: AsRef<Path>,
+    {
+        let mut journal = Self {
+            journal_path: journal_path.as_ref().to_path_buf(),
+            next_id: 0,
+            read_length: 0,
+            mirrored: HashSet::new(),
+            restored: HashMap::new(),
+        };
+        journal.refresh()?;
+        Ok(journal)
+    }
+
+    pub fn get_journal_path(&self) -> &PathBuf {
+        &self.journal_path
+    }
+
+    pub fn read_entries(&self) -> Result<Vec<JournalEntry>, FsmError> {
+        if !self.journal_path.exists() {
+            return Ok(vec![]);
+        }
+        let reader = BufReader::new(File::open(&self.journal_path)?);
+        parse_entries(reader, &self.journal_path)
+    }
+
+    /**
+    Opens the journal file and locks it until the returned file is dropped.
+    */
+    fn lock(&self) -> Result<File, FsmError> {
+        if let Some(parent) = self.journal_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&self.journal_path)?;
+        file.lock()?;
+        Ok(file)
+    }
+
+    /**
+    Reads the entries appended to the locked journal file since it was last read.
+    */
+    fn sync(&mut self, file: &mut File) -> Result<(), FsmError> {
+        if file.metadata()?.len() == self.read_length {
+            return Ok(());
+        }
+        file.seek(SeekFrom::Start(self.read_length))?;
+        let mut appended = String::new();
+        file.read_to_string(&mut appended)?;
+        // only complete lines, a torn final line is read again once it is finished
+        let complete = appended.rfind('\n').map_or(0, |index| index + 1);
+        for entry in parse_entries(&appended.as_bytes()[..complete], &self.journal_path)? {
+            self.apply(&entry);
+        }
+        self.read_length += complete as u64;
+        Ok(())
+    }
+
+    fn apply(&mut self, entry: &JournalEntry) {
+        self.next_id = self.next_id.max(entry.id + 1);
+        match entry.undoes {
+            Some(_) => {
+                self.mirrored.remove(&entry.final_path);
+                self.restored
+                    .insert(entry.final_path.clone(), entry.timestamp);
+            }
+            None => {
+                self.restored.remove(&entry.original_path);
+                if entry.action != FileAction::Move {
+                    self.mirrored.insert(entry.original_path.clone());
+                }
+            }
+        }
+    }
+
+    /**
+    Reads the entries written by other fsm processes since the journal was last read.
+    */
+    pub fn refresh(&mut self) -> Result<(), FsmError> {
+        if !self.journal_path.exists() {
+            return Ok(());
+        }
+        let mut file = self.lock()?;
+        self.sync(&mut file)
+    }
+
+    /**
+    Whether the path was left in place by an earlier copy, link or move-and-leave-symlink, in which case
+    it should not be processed again.
+    */
+    pub fn is_mirrored(&self, path: &Path) -> bool {
+        self.mirrored.contains(path)
+    }
+
+    /**
+    Whether the file at the path was put back there by an undo, in which case it should not be processed
+    again. A file last modified after the undo is a new file and is processed as usual.
+    */
+    pub fn is_restored(&self, path: &Path) -> bool {
+        match self.restored.get(path) {
+            None => false,
+            Some(restored_at) => fs::metadata(path)
+                .and_then(|metadata| metadata.modified())
+                .is_ok_and(|modified| DateTime::<Local>::from(modified) <= *restored_at),
+        }
+    }
+
+    pub fn record_move<P, Q>(
+        &mut self,
+        original_path: P,
+        final_path: Q,
+        action: FileAction,
+        filter_index: usize,
+        directory_key: &str,
+    ) -> Result<JournalEntry, FsmError>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        let entry = JournalEntry {
+            // assigned when appended
+            id: 0,
+            timestamp: Local::now(),
+            original_path: original_path.as_ref().to_path_buf(),
+            final_path: final_path.as_ref().to_path_buf(),
+            action,
+            filter_index,
+            directory_key: directory_key.to_string(),
+            undoes: None,
+        };
+        let mut file = self.lock()?;
+        self.append(&mut file, entry)
+    }
+
+    /**
+    Moves that have not been undone yet, oldest first.
+    */
+    pub fn get_active_moves(&self) -> Result<Vec<JournalEntry>, FsmError> {
+        Ok(get_active(self.read_entries()?))
+    }
+
+    /**
+    Reverses the last `count` moves. Returns the undo entries that were recorded.
+    */
+    pub fn undo_last(&mut self, count: usize) -> Result<Vec<JournalEntry>, FsmError> {
+        let mut file = self.lock()?;
+        let active_moves = get_active(self.read_locked(&mut file)?);
+        let skipped = active_moves.len().saturating_sub(count);
+        self.undo_moves(&mut file, active_moves.into_iter().skip(skipped).collect())
+    }
+
+    /**
+    Reverses every move performed at or after `since`. Returns the undo entries that were recorded.
+    */
+    pub fn undo_since(&mut self, since: DateTime<Local>) -> Result<Vec<JournalEntry>, FsmError> {
+        let mut file = self.lock()?;
+        let active_moves = get_active(self.read_locked(&mut file)?);
+        self.undo_moves(
+            &mut file,
+            active_moves
+                .into_iter()
+                .filter(|entry| entry.timestamp >= since)
+                .collect(),
+        )
+    }
+
+    fn read_locked(&mut self, file: &mut File) -> Result<Vec<JournalEntry>, FsmError> {
+        file.seek(SeekFrom::Start(0))?;
+        parse_entries(BufReader::new(&mut *file), &self.journal_path)
+    }
+
+    /**
+    Reverses the given moves while holding the journal lock, so that a running fsm only sees the
+    restored files once their undo entries are written. Moves that fail to reverse are logged and
+    skipped.
+    */
+    fn undo_moves(
+        &mut self,
+        file: &mut File,
+        moves: Vec<JournalEntry>,
+    ) -> Result<Vec<JournalEntry>, FsmError> {
+        let mut undone = vec![];
+        // most recent moves are reversed first, in case later moves depend on earlier ones
+        for entry in moves.into_iter().rev() {
+            match reverse_move(&entry) {
+                Ok(true) => (),
+                Ok(false) => continue,
+                Err(e) => {
+                    warn!(
+                        "Could not undo {} of {:?} to {:?}: {}",
+                        entry.action,
+                        entry.original_path,
+                        entry.final_path,
+                        e.get_error_message()
+                    );
+                    continue;
+                }
+            }
+            let undo_entry = JournalEntry {
+                id: 0,
+                timestamp: Local::now(),
+                original_path: entry.final_path,
+                final_path: entry.original_path,
+                action: entry.action,
+                filter_index: entry.filter_index,
+                directory_key: entry.directory_key,
+                undoes: Some(entry.id),
+            };
+            undone.push(self.append(file, undo_entry)?);
+        }
+        Ok(undone)
+    }
+
+    /**
+    Appends the entry to the locked journal file under the next free id.
+    */
+    fn append(
+        &mut self,
+        file: &mut File,
+        mut entry: JournalEntry,
+    ) -> Result<JournalEntry, FsmError> {
+        self.sync(file)?;
+        entry.id = self.next_id;
+        let line = format!("{}\n", serde_json::to_string(&entry)?);
+        file.write_all(line.as_bytes())?;
+        self.read_length += line.len() as u64;
+        self.apply(&entry);
+        Ok(entry)
+    }
+}
+
+/**
+Parses a local timestamp given as RFC 3339, `YYYY-MM-DD HH:MM:SS` or `YYYY-MM-DD`.
+*/
+pub fn parse_timestamp(timestamp: &str) -> Result<DateTime<Local>, FsmError> {
+    let timestamp = timestamp.trim();
+    if let Ok(res) = DateTime::parse_from_rfc3339(timestamp) {
+        return Ok(res.with_timezone(&Local));
+    }
+    let naive = NaiveDateTime::parse_from_str(timestamp, "%Y-%m-%d %H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(timestamp, "%Y-%m-%dT%H:%M:%S"))
+        .or_else(|_| {
+            NaiveDate::parse_from_str(timestamp, "%Y-%m-%d")
+                .map(|date| date.and_hms_opt(0, 0, 0).unwrap_or_default())
+        });
+    match naive
+        .ok()
+        .and_then(|res| Local.from_local_datetime(&res).earliest())
+    {
+        Some(res) => Ok(res),
+        None => Err(FsmError::new(
+            ErrorType::JournalError,
+            format!("Invalid timestamp {:?} provided.", timestamp),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Datelike, Timelike};
+    use std::{env, time::SystemTime};
+
+    fn test_directory(name: &str) -> PathBuf {
+        let directory =
+            env::temp_dir().join(format!("fsm_journal_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(directory.join("watched")).unwrap();
+        fs::create_dir_all(directory.join("managed")).unwrap();
+        directory
+    }
+
+    /**
+    Moves a file from the watched to the managed directory and records it.
+    */
+    fn move_and_record(journal: &mut MoveJournal, directory: &Path, name: &str) -> JournalEntry {
+        let original_path = directory.join("watched").join(name);
+        let final_path = directory.join("managed").join(name);
+        fs::write(&final_path, name).unwrap();
+        journal
+            .record_move(&original_path, &final_path, FileAction::Move, 0, "key")
+            .unwrap()
+    }
+
+    #[test]
+    fn parses_timestamps() {
+        let parsed = parse_timestamp("2024-05-03").unwrap();
+        assert_eq!((parsed.year(), parsed.month(), parsed.day()), (2024, 5, 3));
+        assert_eq!((parsed.hour(), parsed.minute()), (0, 0));
+
+        for timestamp in [
+            "2024-05-03 18:30:15",
+            "2024-05-03T18:30:15",
+            " 2024-05-03 18:30:15 ",
+        ] {
+            let parsed = parse_timestamp(timestamp).unwrap();
+            assert_eq!(
+                (
+                    parsed.day(),
+                    parsed.hour(),
+                    parsed.minute(),
+                    parsed.second()
+                ),
+                (3, 18, 30, 15),
+                "{}",
+                timestamp
+            );
+        }
+
+        let parsed = parse_timestamp("2024-05-03T18:30:15Z").unwrap();
+        assert_eq!(
+            parsed,
+            DateTime::parse_from_rfc3339("2024-05-03T18:30:15Z").unwrap()
+        );
+
+        for timestamp in ["", "yesterday", "2024-13-01", "03/05/2024"] {
+            let error = parse_timestamp(timestamp).unwrap_err();
+            assert!(matches!(error.get_error_type(), ErrorType::JournalError));
+        }
+    }
+
+    #[test]
+    fn undoes_last_moves() {
+        let directory = test_directory("undo_last");
+        let mut journal = MoveJournal::open(directory.join("journal.jsonl")).unwrap();
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            move_and_record(&mut journal, &directory, name);
+        }
+
+        let undone = journal.undo_last(2).unwrap();
+        let restored: Vec<&Path> = undone
+            .iter()
+            .map(|entry| entry.final_path.as_path())
+            .collect();
+        assert_eq!(
+            restored,
+            [
+                directory.join("watched/c.txt"),
+                directory.join("watched/b.txt")
+            ]
+        );
+        assert_eq!(
+            undone
+                .iter()
+                .map(|entry| (entry.id, entry.undoes))
+                .collect::<Vec<_>>(),
+            [(3, Some(2)), (4, Some(1))]
+        );
+        assert!(directory.join("watched/b.txt").exists());
+        assert!(!directory.join("managed/b.txt").exists());
+        assert!(directory.join("managed/a.txt").exists());
+
+        // only moves that were not undone yet are reversed
+        let undone = journal.undo_last(5).unwrap();
+        assert_eq!(undone.len(), 1);
+        assert_eq!(undone[0].undoes, Some(0));
+        assert!(journal.get_active_moves().unwrap().is_empty());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn keeps_undoing_after_a_failure() {
+        let directory = test_directory("undo_failure");
+        let mut journal = MoveJournal::open(directory.join("journal.jsonl")).unwrap();
+        move_and_record(&mut journal, &directory, "a.txt");
+        // the original directory of this one can no longer be created
+        let blocked = directory.join("watched/blocked/b.txt");
+        fs::write(directory.join("watched/blocked"), "").unwrap();
+        fs::write(directory.join("managed/b.txt"), "b").unwrap();
+        journal
+            .record_move(
+                &blocked,
+                directory.join("managed/b.txt"),
+                FileAction::Move,
+                0,
+                "key",
+            )
+            .unwrap();
+        move_and_record(&mut journal, &directory, "c.txt");
+
+        let undone = journal.undo_last(3).unwrap();
+        assert_eq!(
+            undone.iter().map(|entry| entry.undoes).collect::<Vec<_>>(),
+            [Some(2), Some(0)]
+        );
+        assert!(directory.join("watched/a.txt").exists());
+        assert!(directory.join("managed/b.txt").exists());
+        assert_eq!(journal.get_active_moves().unwrap().len(), 1);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn sees_entries_of_other_processes() {
+        let directory = test_directory("shared");
+        let journal_path = directory.join("journal.jsonl");
+        let mut daemon = MoveJournal::open(&journal_path).unwrap();
+        move_and_record(&mut daemon, &directory, "a.txt");
+        move_and_record(&mut daemon, &directory, "b.txt");
+
+        // e.g. `fsm undo` while fsm is running
+        let mut undo = MoveJournal::open(&journal_path).unwrap();
+        undo.undo_last(1).unwrap();
+
+        let entry = move_and_record(&mut daemon, &directory, "c.txt");
+        let ids: Vec<u64> = daemon
+            .read_entries()
+            .unwrap()
+            .iter()
+            .map(|entry| entry.id)
+            .collect();
+        assert_eq!(ids, [0, 1, 2, 3]);
+        assert_eq!(entry.id, 3);
+
+        daemon.refresh().unwrap();
+        let restored = directory.join("watched/b.txt");
+        assert!(daemon.is_restored(&restored));
+        assert!(!daemon.is_restored(&directory.join("watched/a.txt")));
+
+        // a file modified after the undo is processed again
+        let modified = fs::File::options().write(true).open(&restored).unwrap();
+        modified
+            .set_modified(SystemTime::now() + std::time::Duration::from_secs(60))
+            .unwrap();
+        assert!(!daemon.is_restored(&restored));
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn tracks_mirrored_paths() {
+        let directory = test_directory("mirrored");
+        let journal_path = directory.join("journal.jsonl");
+        let original_path = directory.join("watched/a.txt");
+        let final_path = directory.join("managed/a.txt");
+        fs::write(&original_path, "a").unwrap();
+        fs::write(&final_path, "a").unwrap();
+        let mut journal = MoveJournal::open(&journal_path).unwrap();
+        journal
+            .record_move(&original_path, &final_path, FileAction::Copy, 0, "key")
+            .unwrap();
+        assert!(journal.is_mirrored(&original_path));
+        assert!(MoveJournal::open(&journal_path)
+            .unwrap()
+            .is_mirrored(&original_path));
+
+        MoveJournal::open(&journal_path)
+            .unwrap()
+            .undo_last(1)
+            .unwrap();
+        assert!(!final_path.exists());
+        journal.refresh().unwrap();
+        assert!(!journal.is_mirrored(&original_path));
+        assert!(journal.is_restored(&original_path));
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
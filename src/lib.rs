@@ -1,85 +1,604 @@
+use std::fmt::Display;
 use std::path::Path;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::time::Duration;
 
-use config::ConfigManager;
+use config::{ConfigManager, WatchRoot};
 use data::data_receiver::logger::Logger;
 use data::data_receiver::writer::PathWriter;
-use data::data_source::sweep::DirectorySweeper;
+use data::data_source::sweep::{DirectorySweeper, SweepTrigger};
 use data::data_source::watch::DirectoryWatcher;
-use data::data_source::DataSource;
+use data::data_source::{DataSource, Publisher};
 use data::MessageManager;
+use data::MoveStatistics;
+use data::RecentWrites;
+use data::{LogType, Message};
+use error::ErrorType;
 use error::FsmError;
+use exclude::ExcludeSet;
 use filepath::FilepathManager;
 use filter::FilterManager;
-use reader::read_fsm_config;
-use reader::TimeInterval;
+use hash::HashIndex;
+use journal::MoveJournal;
+use reader::{read_fsm_config, FsmConfigRepresentation};
+use schedule::SweepSchedule;
 
 pub mod config;
 pub mod data;
 pub mod error;
+pub mod exclude;
+pub mod expand;
 pub mod filepath;
 pub mod filter;
+pub mod hash;
+pub mod journal;
 pub mod reader;
+pub mod schedule;
+pub mod stability;
+pub mod transfer;
+pub mod validate;
 
 pub struct FsmState {
     pub config_manager: Arc<Mutex<ConfigManager>>,
     pub filepath_manager: Arc<Mutex<FilepathManager>>,
     pub filter_manager: Arc<Mutex<FilterManager>>,
+    pub journal: Arc<Mutex<MoveJournal>>,
+    pub hash_index: Arc<Mutex<HashIndex>>,
     pub message_manager: MessageManager,
+    pub sweep_trigger: Option<SweepTrigger>,
+    pub statistics: Arc<MoveStatistics>,
+    config_file_path: PathBuf,
+    options: FsmOptions,
+    recent_writes: Arc<Mutex<RecentWrites>>,
+    publisher: Publisher,
+    paused: bool,
+}
+
+/**
+Totals of the move outcomes, returned when fsm shuts down.
+*/
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FsmSummary {
+    pub moved: usize,
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+impl Display for FsmSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Moved {} files, skipped {} and failed to move {}.",
+            self.moved, self.skipped, self.failed
+        )
+    }
+}
+
+impl FsmState {
+    /**
+    Sweeps every watch root right away, in addition to the scheduled sweeps.
+    */
+    pub fn sweep_now(&self) -> Result<(), FsmError> {
+        match &self.sweep_trigger {
+            Some(sweep_trigger) => sweep_trigger.sweep_now(),
+            None => Err(FsmError::new(
+                ErrorType::SweepError,
+                "The sweeper is not running.".to_string(),
+            )),
+        }
+    }
+
+    /**
+    Stops picking up new files. Moves that are already queued still go ahead.
+    */
+    pub fn pause(&mut self) {
+        self.message_manager.pause_sources();
+        self.paused = true;
+    }
+
+    /**
+    Starts picking up files again, sweeping right away to catch up on files that arrived while paused.
+    Failing to start that sweep does not undo the resume, it is only reported.
+    */
+    pub fn resume(&mut self) -> Result<(), FsmError> {
+        self.message_manager.resume_sources();
+        self.paused = false;
+        if let Err(e) = self.sweep_now() {
+            self.publisher.publish(Message::Log {
+                message: format!(
+                    "Resumed, but could not sweep right away: {}",
+                    e.get_error_message()
+                ),
+                message_type: LogType::Warning,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /**
+    Re-reads the config file and, if it is valid, swaps in the new settings, filters and managed
+    directories and restarts the sweeper and watcher with them. A config that is invalid or whose
+    roots cannot be watched is rejected with the previous one left running. The number of move
+    workers and their queue size only change on restart.
+    */
+    pub fn reload(&mut self) -> Result<(), FsmError> {
+        let fsm_config = read_fsm_config(&self.config_file_path)?;
+        let FsmManagers {
+            config_manager,
+            filepath_manager,
+            filter_manager,
+            journal_path,
+            sweep_loop_time,
+        } = build_managers(fsm_config, &self.options)?;
+
+        // the journal and hash index move along with the managed path
+        let journal = match self.journal.lock() {
+            Err(e) => return Err(FsmError::new(ErrorType::ApplicationError, e.to_string())),
+            Ok(res) if *res.get_journal_path() == journal_path => None,
+            Ok(_) => Some(MoveJournal::open(journal_path)?),
+        };
+        let hash_index_path = config_manager.get_hash_index_path();
+        let hash_index = match self.hash_index.lock() {
+            Err(e) => return Err(FsmError::new(ErrorType::ApplicationError, e.to_string())),
+            Ok(res) if *res.get_index_path() == hash_index_path => None,
+            Ok(_) => Some(HashIndex::open(hash_index_path)?),
+        };
+
+        // the new roots are watched before anything is torn down, paused until the old watcher stops
+        let directory_watcher = DirectoryWatcher::from_config(
+            &config_manager,
+            self.message_manager.get_receivers(),
+            self.recent_writes.clone(),
+        )?;
+        directory_watcher.pause();
+
+        // the old sources are replaced either way, so failing to stop them does not reject the reload
+        self.sweep_trigger = None;
+        if let Err(e) = self.message_manager.remove_sources() {
+            self.publisher.publish(Message::Log {
+                message: format!(
+                    "Could not cleanly stop the previous sweeper and watcher: {}",
+                    e.get_error_message()
+                ),
+                message_type: LogType::Warning,
+            });
+        }
+
+        // moves already queued pick up the new managers as they go
+        match self.config_manager.lock() {
+            Err(e) => return Err(FsmError::new(ErrorType::ApplicationError, e.to_string())),
+            Ok(mut res) => *res = config_manager,
+        };
+        match self.filepath_manager.lock() {
+            Err(e) => return Err(FsmError::new(ErrorType::ApplicationError, e.to_string())),
+            Ok(mut res) => *res = filepath_manager,
+        };
+        match self.filter_manager.lock() {
+            Err(e) => return Err(FsmError::new(ErrorType::ApplicationError, e.to_string())),
+            Ok(mut res) => *res = filter_manager,
+        };
+        if let Some(journal) = journal {
+            match self.journal.lock() {
+                Err(e) => return Err(FsmError::new(ErrorType::ApplicationError, e.to_string())),
+                Ok(mut res) => *res = journal,
+            };
+        }
+        if let Some(hash_index) = hash_index {
+            match self.hash_index.lock() {
+                Err(e) => return Err(FsmError::new(ErrorType::ApplicationError, e.to_string())),
+                Ok(mut res) => *res = hash_index,
+            };
+        }
+
+        self.sweep_trigger = start_sources(
+            &mut self.message_manager,
+            &self.config_manager,
+            directory_watcher,
+            sweep_loop_time,
+        );
+        match self.paused {
+            true => self.message_manager.pause_sources(),
+            false => self.message_manager.resume_sources(),
+        }
+        Ok(())
+    }
+
+    /**
+    Stops every data source, waits for the queued moves to finish and returns the totals of the
+    moves made since startup.
+    */
+    pub fn shutdown(&mut self) -> Result<FsmSummary, FsmError> {
+        self.sweep_trigger = None;
+        let stopped = self.message_manager.stop_sources();
+        self.message_manager.shutdown_receivers();
+        // the outcomes of the last moves may still be on their way to the statistics
+        self.publisher.wait_until_published();
+        stopped?;
+        Ok(FsmSummary {
+            moved: self.statistics.get_moved(),
+            skipped: self.statistics.get_skipped(),
+            failed: self.statistics.get_failed(),
+        })
+    }
+}
+
+/**
+Options that override the behaviour set in the config file (e.g. from the command line).
+*/
+#[derive(Debug, Default, Clone)]
+pub struct FsmOptions {
+    pub dry_run: bool,
+}
+
+/**
+Journal location from the config, defaulting to `fsm_journal.jsonl` in the managed path.
+*/
+pub fn get_journal_path(manage_path: &Path, journal_path: Option<&str>) -> PathBuf {
+    match journal_path {
+        Some(res) => PathBuf::from(res),
+        None => manage_path.join("fsm_journal.jsonl"),
+    }
 }
 
 pub fn init_fsm(config_file_path: impl AsRef<Path>) -> Result<FsmState, FsmError> {
-    let fsm_config = read_fsm_config(config_file_path)?;
+    init_fsm_with_options(config_file_path, FsmOptions::default())
+}
+
+/**
+Managers built from a freshly read config, not yet shared with the rest of fsm.
+*/
+struct FsmManagers {
+    config_manager: ConfigManager,
+    filepath_manager: FilepathManager,
+    filter_manager: FilterManager,
+    journal_path: PathBuf,
+    sweep_loop_time: Duration,
+}
 
+fn build_managers(
+    fsm_config: FsmConfigRepresentation,
+    options: &FsmOptions,
+) -> Result<FsmManagers, FsmError> {
     // init basic managers
-    let config_manager = ConfigManager::new(
-        fsm_config.watch_path,
+    let collision_strategy = fsm_config.get_collision_strategy();
+    let watch_roots = fsm_config.get_watch_roots();
+    let mut config_manager = ConfigManager::new(
+        vec![],
         fsm_config.managed_path,
-        fsm_config.overwrite_on_move,
+        collision_strategy,
+        fsm_config.on_duplicate,
+        fsm_config.dry_run || options.dry_run,
     );
 
-    let filepath_manager = FilepathManager::new(
+    if let Some(stability_window) = fsm_config.stability_window {
+        config_manager.set_stability_window(stability_window.into());
+    }
+    if let Some(temporary_suffixes) = fsm_config.temporary_suffixes {
+        config_manager.set_temporary_suffixes(temporary_suffixes);
+    }
+    if let Some(watch_events) = fsm_config.watch_events {
+        config_manager.set_watch_events(watch_events);
+    }
+    config_manager.set_exclude(ExcludeSet::new(fsm_config.exclude)?);
+    if let Some(move_workers) = fsm_config.move_workers {
+        config_manager.set_move_workers(move_workers);
+    }
+    if let Some(move_queue_size) = fsm_config.move_queue_size {
+        config_manager.set_move_queue_size(move_queue_size);
+    }
+
+    let mut filepath_manager = FilepathManager::new(
         config_manager.get_manage_path(),
         &fsm_config.managed_directory_structure,
+        !config_manager.is_dry_run(),
     )?;
-    let filepath_manager = Arc::new(Mutex::new(filepath_manager));
+    let mut filter_manager = FilterManager::new(fsm_config.filters);
 
-    let filter_manager = FilterManager::new(fsm_config.filters);
+    for watch_root in watch_roots {
+        let managed_path = match watch_root.managed_path {
+            Some(res) => {
+                let managed_path = PathBuf::from(res);
+                filepath_manager.add_managed_root(
+                    &managed_path,
+                    &fsm_config.managed_directory_structure,
+                    !config_manager.is_dry_run(),
+                )?;
+                managed_path
+            }
+            None => config_manager.get_manage_path().clone(),
+        };
+        if let Some(filters) = watch_root.filters {
+            filter_manager.set_root_filters(&watch_root.path, filters);
+        }
+        let max_depth = watch_root.max_depth.or(fsm_config.max_depth);
+        // like the original `watch_path`, roots are not recursive unless asked to be
+        let recursive = watch_root.recursive.unwrap_or(max_depth.is_some());
+        let mut new_watch_root = WatchRoot::new(watch_root.path, recursive, managed_path);
+        new_watch_root.set_max_depth(max_depth);
+        new_watch_root
+            .set_directory_handling(watch_root.directories.unwrap_or(fsm_config.directories));
+        new_watch_root.set_schedule(watch_root.schedule);
+        config_manager.add_watch_root(new_watch_root)?;
+    }
+
+    // each distinct schedule gets its own sweeps
+    let mut schedules: Vec<SweepSchedule> = vec![];
+    let root_schedules = config_manager
+        .get_watch_roots()
+        .iter()
+        .filter_map(|watch_root| watch_root.get_schedule());
+    let filter_schedules = filter_manager
+        .get_all_filters()
+        .filter_map(|filter| filter.get_schedule());
+    for schedule in root_schedules.chain(filter_schedules) {
+        if !schedules.contains(schedule) {
+            schedules.push(schedule.clone());
+        }
+    }
+    config_manager.set_schedules(schedules);
+
+    let journal_path = get_journal_path(
+        config_manager.get_manage_path(),
+        fsm_config.journal_path.as_deref(),
+    );
+    Ok(FsmManagers {
+        config_manager,
+        filepath_manager,
+        filter_manager,
+        journal_path,
+        sweep_loop_time: fsm_config.sweep_loop_time.unwrap_or_default().into(),
+    })
+}
+
+/**
+Starts the sweeper and adds it along with the watcher, returning the trigger for on-demand sweeps.
+*/
+fn start_sources(
+    message_manager: &mut MessageManager,
+    config_manager: &Arc<Mutex<ConfigManager>>,
+    directory_watcher: DirectoryWatcher,
+    sweep_loop_time: Duration,
+) -> Option<SweepTrigger> {
+    let mut directory_sweeper = DirectorySweeper::new(Arc::new(Mutex::new(sweep_loop_time)));
+    directory_sweeper.set_receivers(message_manager.get_receivers());
+
+    let _ = directory_sweeper.start_sweep(config_manager.clone());
+    let sweep_trigger = directory_sweeper.get_trigger();
+
+    message_manager.add_source(Box::new(directory_sweeper));
+    message_manager.add_source(Box::new(directory_watcher));
+    sweep_trigger
+}
+
+pub fn init_fsm_with_options(
+    config_file_path: impl AsRef<Path>,
+    options: FsmOptions,
+) -> Result<FsmState, FsmError> {
+    let config_file_path = config_file_path.as_ref().to_path_buf();
+    let fsm_config = read_fsm_config(&config_file_path)?;
+    let FsmManagers {
+        config_manager,
+        filepath_manager,
+        filter_manager,
+        journal_path,
+        sweep_loop_time,
+    } = build_managers(fsm_config, &options)?;
+
+    let filepath_manager = Arc::new(Mutex::new(filepath_manager));
     let filter_manager = Arc::new(Mutex::new(filter_manager));
 
+    let journal = MoveJournal::open(journal_path)?;
+    let journal = Arc::new(Mutex::new(journal));
+
+    let hash_index = HashIndex::open(config_manager.get_hash_index_path())?;
+    let hash_index = Arc::new(Mutex::new(hash_index));
+
+    let config_manager = Arc::new(Mutex::new(config_manager));
+    let recent_writes = Arc::new(Mutex::new(RecentWrites::new()));
+
     let mut message_manager = MessageManager::new();
     // add receivers
-    message_manager.add_receiver(Box::new(Logger::new()));
+    let publisher = Publisher::new(message_manager.get_receivers());
+    let logger = Logger::new();
+    let statistics = logger.get_statistics();
+    message_manager.add_receiver(Box::new(logger));
 
     let file_writer = PathWriter::new(
-        config_manager.perform_overwrite_on_move(),
+        config_manager.clone(),
         filepath_manager.clone(),
         filter_manager.clone(),
+        journal.clone(),
+        hash_index.clone(),
+        recent_writes.clone(),
+        publisher.clone(),
     );
     message_manager.add_receiver(Box::new(file_writer));
 
     // add data sources
-    let sweep_loop_time = fsm_config.sweep_loop_time.unwrap_or_default();
-    let mut directory_sweeper = DirectorySweeper::new(Arc::new(Mutex::new(sweep_loop_time.into())));
-    directory_sweeper.set_receivers(message_manager.get_receivers());
-
-    // todo - deal with clone here
-    let _ = directory_sweeper.start_sweep(config_manager.get_watch_path().clone());
-
-    let config_manager = Arc::new(Mutex::new(config_manager));
-    message_manager.add_source(Box::new(directory_sweeper));
-
-    message_manager.add_source(Box::new(
-        DirectoryWatcher::new(config_manager.clone(), message_manager.get_receivers()).unwrap(),
-    ));
+    let directory_watcher = DirectoryWatcher::new(
+        config_manager.clone(),
+        message_manager.get_receivers(),
+        recent_writes.clone(),
+    )?;
+    let sweep_trigger = start_sources(
+        &mut message_manager,
+        &config_manager,
+        directory_watcher,
+        sweep_loop_time,
+    );
 
-    // add data receivers
     Ok(FsmState {
         config_manager,
         filepath_manager,
         filter_manager,
+        journal,
+        hash_index,
         message_manager,
+        sweep_trigger,
+        statistics,
+        config_file_path,
+        options,
+        recent_writes,
+        publisher,
+        paused: false,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::thread;
+    use std::time::Instant;
+
+    fn test_directory(name: &str) -> PathBuf {
+        let directory = env::temp_dir().join(format!("fsm_lib_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(directory.join("watched")).unwrap();
+        fs::create_dir_all(directory.join("managed")).unwrap();
+        directory
+    }
+
+    /**
+    Writes a config moving files with the given extension from `watched` to `managed`, swept only
+    on request.
+    */
+    fn write_config(directory: &Path, extension: &str) -> PathBuf {
+        let config_path = directory.join("fsm_config.json");
+        let config = serde_json::json!({
+            "watch_path": directory.join("watched"),
+            "managed_path": directory.join("managed"),
+            "managed_directory_structure": {"a": "a"},
+            "filters": [{"allowed_filetypes": [extension], "directory_key": "a"}],
+            "sweep_loop_time": {"hours": 1},
+            "stability_window": {"seconds": 0},
+        });
+        fs::write(&config_path, config.to_string()).unwrap();
+        config_path
+    }
+
+    fn get_managed_path(directory: &Path, name: &str) -> PathBuf {
+        directory
+            .join("managed")
+            .join("categorized")
+            .join("a")
+            .join(name)
+    }
+
+    fn wait_for(condition: impl Fn() -> bool) -> bool {
+        let started = Instant::now();
+        while started.elapsed() < Duration::from_secs(5) {
+            if condition() {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        false
+    }
+
+    #[test]
+    fn picks_up_files_again_once_resumed() {
+        let directory = test_directory("pause");
+        let config_path = write_config(&directory, "txt");
+        let mut fsm = init_fsm(&config_path).unwrap();
+
+        fsm.pause();
+        assert!(fsm.is_paused());
+        let notes = directory.join("watched").join("notes.txt");
+        fs::write(&notes, "notes").unwrap();
+        thread::sleep(Duration::from_millis(300));
+        assert!(fsm.sweep_now().is_ok());
+        thread::sleep(Duration::from_millis(300));
+        assert!(notes.exists());
+
+        assert!(fsm.resume().is_ok());
+        assert!(!fsm.is_paused());
+        assert!(wait_for(
+            || get_managed_path(&directory, "notes.txt").exists()
+        ));
+
+        let summary = fsm.shutdown().unwrap();
+        assert_eq!(summary.moved, 1);
+        assert_eq!(summary.failed, 0);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn resumes_without_a_sweeper() {
+        let directory = test_directory("resume");
+        let config_path = write_config(&directory, "txt");
+        let mut fsm = init_fsm(&config_path).unwrap();
+        fsm.pause();
+        fsm.sweep_trigger = None;
+        assert!(fsm.resume().is_ok());
+        assert!(!fsm.is_paused());
+        fsm.shutdown().unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn finishes_queued_moves_on_shutdown() {
+        let directory = test_directory("shutdown");
+        for index in 0..20 {
+            let file = directory.join("watched").join(format!("{}.txt", index));
+            fs::write(file, "notes").unwrap();
+        }
+        let config_path = write_config(&directory, "txt");
+        let mut fsm = init_fsm(&config_path).unwrap();
+        assert!(wait_for(|| fsm.statistics.get_moved() > 0));
+
+        let summary = fsm.shutdown().unwrap();
+        assert_eq!(summary.moved, 20);
+        assert!(fs::read_dir(directory.join("watched"))
+            .unwrap()
+            .next()
+            .is_none());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn swaps_filters_on_reload() {
+        let directory = test_directory("reload");
+        let config_path = write_config(&directory, "txt");
+        let mut fsm = init_fsm(&config_path).unwrap();
+
+        write_config(&directory, "pdf");
+        fsm.reload().unwrap();
+        let notes = directory.join("watched").join("notes.txt");
+        fs::write(&notes, "notes").unwrap();
+        fs::write(directory.join("watched").join("report.pdf"), "report").unwrap();
+        fsm.sweep_now().unwrap();
+        assert!(wait_for(
+            || get_managed_path(&directory, "report.pdf").exists()
+        ));
+        assert!(notes.exists());
+
+        fsm.shutdown().unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn keeps_the_running_config_when_a_reload_fails() {
+        let directory = test_directory("bad_reload");
+        let config_path = write_config(&directory, "txt");
+        let mut fsm = init_fsm(&config_path).unwrap();
+
+        fs::write(&config_path, "{ not a config").unwrap();
+        assert!(fsm.reload().is_err());
+        fs::write(directory.join("watched").join("notes.txt"), "notes").unwrap();
+        fsm.sweep_now().unwrap();
+        assert!(wait_for(
+            || get_managed_path(&directory, "notes.txt").exists()
+        ));
+
+        fsm.shutdown().unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
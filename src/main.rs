@@ -1,30 +1,206 @@
 use std::io::{self};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
 
-use env_logger;
-
+use fsm::error::ErrorType;
 use fsm::error::FsmError;
-use fsm::init_fsm;
+use fsm::expand::expand_path;
+use fsm::filter::FileAction;
+use fsm::get_journal_path;
+use fsm::init_fsm_with_options;
+use fsm::journal::parse_timestamp;
+use fsm::journal::MoveJournal;
+use fsm::reader::convert_fsm_config;
+use fsm::reader::parse_fsm_config;
+use fsm::reader::watch_fsm_config;
+use fsm::FsmOptions;
+use log::error;
 use log::info;
+use log::warn;
+
+// the first of these that exists is used
+const CONFIG_FILE_PATHS: [&str; 4] = [
+    "fsm_config.json",
+    "fsm_config.toml",
+    "fsm_config.yaml",
+    "fsm_config.yml",
+];
+
+fn get_config_file_path() -> &'static str {
+    CONFIG_FILE_PATHS
+        .iter()
+        .find(|path| Path::new(path).exists())
+        .unwrap_or(&CONFIG_FILE_PATHS[0])
+}
+
+/**
+Handles `fsm convert <from> <to>`.
+*/
+fn convert(args: &[String]) -> Result<(), FsmError> {
+    match args {
+        [from, to] => {
+            convert_fsm_config(from, to)?;
+            info!("Converted {:?} to {:?}.", from, to);
+            Ok(())
+        }
+        _ => Err(FsmError::new(
+            ErrorType::ApplicationError,
+            "Usage: fsm convert <from> <to>, e.g. fsm convert fsm_config.json fsm_config.toml"
+                .to_string(),
+        )),
+    }
+}
+
+/**
+Handles `fsm undo <count>` and `fsm undo --since <timestamp>`.
+*/
+fn undo(args: &[String]) -> Result<(), FsmError> {
+    // the rest of the config may not be valid on this machine, or anymore
+    let fsm_config = parse_fsm_config(get_config_file_path())?;
+    let managed_path = expand_path(&fsm_config.managed_path)?;
+    let journal_path = match fsm_config.journal_path.as_deref() {
+        Some(res) => Some(expand_path(res)?),
+        None => None,
+    };
+    let mut journal = MoveJournal::open(get_journal_path(
+        managed_path.as_ref(),
+        journal_path.as_deref(),
+    ))?;
+    let undone = match args {
+        [flag, since] if flag == "--since" => journal.undo_since(parse_timestamp(since)?)?,
+        [count] => match count.parse::<usize>() {
+            Ok(res) => journal.undo_last(res)?,
+            Err(e) => return Err(FsmError::new(ErrorType::ApplicationError, e.to_string())),
+        },
+        _ => {
+            return Err(FsmError::new(
+                ErrorType::ApplicationError,
+                "Usage: fsm undo <count> | fsm undo --since <timestamp>".to_string(),
+            ))
+        }
+    };
+    for entry in undone.iter() {
+        match entry.action {
+            FileAction::Move | FileAction::MoveAndLeaveSymlink => info!(
+                "Moved {:?} back to {:?}.",
+                entry.original_path, entry.final_path
+            ),
+            _ => info!(
+                "Removed {} {:?} of {:?}.",
+                entry.action, entry.original_path, entry.final_path
+            ),
+        }
+    }
+    info!("Undid {} moves.", undone.len());
+    Ok(())
+}
 
 fn main() -> Result<(), FsmError> {
-    env_logger::init();
-    let app_state = init_fsm("fsm_config.json")?;
-    info!("Running FSM. Provide stdin to modify app behavior.");
+    // fsm reports what it does at the info level
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().is_some_and(|arg| arg == "undo") {
+        return undo(&args[1..]);
+    }
+    if args.first().is_some_and(|arg| arg == "convert") {
+        return convert(&args[1..]);
+    }
+
+    let mut options = FsmOptions::default();
+    for arg in args {
+        match arg.as_str() {
+            "--dry-run" => options.dry_run = true,
+            _ => warn!("Ignoring unknown argument {:?}.", arg),
+        }
+    }
+    let config_file_path = get_config_file_path();
+    let mut app_state = init_fsm_with_options(config_file_path, options)?;
+    if app_state
+        .config_manager
+        .lock()
+        .is_ok_and(|config_manager| config_manager.is_dry_run())
+    {
+        info!("Dry run enabled, planned moves will be reported without touching the disk.");
+    }
+    info!(
+        "Running FSM. Enter \"sweep\" to sweep the watched directories now, \"pause\" or \"resume\" to stop or restart picking up files, \"reload\" to re-read the config, or \"quit\" to exit."
+    );
 
-    loop {
+    // stdin and SIGINT / SIGTERM both feed the same commands
+    let (tx, rx) = mpsc::channel::<String>();
+    let signal_tx = tx.clone();
+    let exiting = Arc::new(AtomicBool::new(false));
+    let signal_exiting = exiting.clone();
+    if let Err(e) = ctrlc::set_handler(move || {
+        // a signal while already exiting gives up on the moves still queued
+        if signal_exiting.swap(true, Ordering::Relaxed) {
+            warn!("Exiting without waiting for the queued moves.");
+            std::process::exit(130);
+        }
+        let _ = signal_tx.send("quit".to_string());
+    }) {
+        warn!("Could not set up the signal handler: {}", e);
+    }
+    let reload_tx = tx.clone();
+    // kept alive for as long as fsm runs
+    let _config_watcher = match watch_fsm_config(config_file_path, move || {
+        let _ = reload_tx.send("reload".to_string());
+    }) {
+        Ok(res) => Some(res),
+        Err(e) => {
+            warn!("Could not watch {} for changes: {}", config_file_path, e);
+            None
+        }
+    };
+    thread::spawn(move || loop {
         let mut buffer = String::new();
-        let _ = match io::stdin().read_line(&mut buffer) {
-            Ok(res) => res,
+        match io::stdin().read_line(&mut buffer) {
+            // stdin was closed, keep running until a signal arrives
+            Ok(0) => break,
+            Ok(_) => {
+                if tx.send(buffer).is_err() {
+                    break;
+                }
+            }
             Err(_) => continue,
         };
-        match buffer.trim().to_lowercase().as_str() {
+    });
+
+    for command in rx {
+        match command.trim().to_lowercase().as_str() {
             "q" | "quit" => {
                 break;
             }
+            "s" | "sweep" => {
+                if let Err(e) = app_state.sweep_now() {
+                    error!("Could not start a sweep: {}", e);
+                }
+            }
+            "p" | "pause" => {
+                app_state.pause();
+                info!("Paused, enter \"resume\" to continue.");
+            }
+            "r" | "resume" => match app_state.resume() {
+                Ok(_) => info!("Resumed."),
+                Err(e) => error!("Could not resume: {}", e),
+            },
+            "reload" => match app_state.reload() {
+                Ok(_) => info!("Reloaded {}.", config_file_path),
+                Err(e) => error!(
+                    "Keeping the previous config, could not reload {}: {}",
+                    config_file_path, e
+                ),
+            },
             _ => continue,
         };
     }
 
-    info!("Exiting");
+    exiting.store(true, Ordering::Relaxed);
+    info!("Exiting, waiting for in-flight moves to finish. Press Ctrl-C again to exit right away.");
+    let summary = app_state.shutdown()?;
+    info!("{}", summary);
     Ok(())
 }
@@ -1,4 +1,11 @@
-use std::{collections::HashMap, fs, io::BufReader, path::Path, time::Duration};
+use std::{fs, path::Path, time::Duration};
+
+use indexmap::IndexMap;
+
+use notify::{RecommendedWatcher, RecursiveMode};
+use notify_debouncer_full::{
+    new_debouncer, notify::Watcher, DebounceEventResult, Debouncer, FileIdMap,
+};
 
 extern crate serde;
 extern crate serde_derive;
@@ -6,19 +13,37 @@ extern crate serde_json;
 
 use serde::{Deserialize, Serialize};
 
-use crate::{error::FsmError, filter::FileFilter};
+use crate::{
+    config::DirectoryHandling,
+    data::data_source::watch::WatchEventKind,
+    error::{ErrorType, FsmError},
+    filter::{CollisionStrategy, DuplicateStrategy, FileFilter},
+    schedule::SweepSchedule,
+    validate::{expand_fsm_config_paths, validate_fsm_config, ConfigProblem},
+};
 
+// kept in the order they are written, so that converted configs read the same
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(untagged)]
 pub enum DirectoryEntry {
     LeafDirectory(String),
-    ParentDirectory(HashMap<String, DirectoryEntry>),
+    ParentDirectory(IndexMap<String, DirectoryEntry>),
+}
+
+/**
+Used to leave settings at their default out of written configs.
+*/
+pub(crate) fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TimeInterval {
+    #[serde(skip_serializing_if = "Option::is_none")]
     seconds: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     minutes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     hours: Option<u64>,
 }
 
@@ -42,30 +67,357 @@ impl Default for TimeInterval {
     }
 }
 
-impl Into<Duration> for TimeInterval {
-    fn into(self) -> Duration {
-        Duration::from_secs(self.seconds.unwrap_or(0))
-            + Duration::from_secs(self.minutes.unwrap_or(0) * 60)
-            + Duration::from_secs(self.hours.unwrap_or(0) * 3600)
+impl From<TimeInterval> for Duration {
+    fn from(interval: TimeInterval) -> Self {
+        Duration::from_secs(interval.seconds.unwrap_or(0))
+            + Duration::from_secs(interval.minutes.unwrap_or(0) * 60)
+            + Duration::from_secs(interval.hours.unwrap_or(0) * 3600)
     }
 }
 
+/**
+A directory to watch. Roots without their own `filters`, `managed_path`, `max_depth` or `directories`
+use the top-level ones. Only the top level of a root is watched unless it sets `recursive` or a
+`max_depth` applies to it.
+*/
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WatchRootRepresentation {
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recursive: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filters: Option<Vec<FileFilter>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub managed_path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_depth: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub directories: Option<DirectoryHandling>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<SweepSchedule>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct FsmConfigRepresentation {
-    pub watch_path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub watch_path: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub watch_roots: Vec<WatchRootRepresentation>,
     pub managed_path: String,
     pub managed_directory_structure: DirectoryEntry,
+    #[serde(default)]
     pub filters: Vec<FileFilter>,
+    #[serde(default, skip_serializing_if = "is_default")]
     pub overwrite_on_move: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on_collision: Option<CollisionStrategy>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub on_duplicate: DuplicateStrategy,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub dry_run: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub sweep_loop_time: Option<TimeInterval>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub journal_path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stability_window: Option<TimeInterval>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temporary_suffixes: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub watch_events: Option<Vec<WatchEventKind>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_depth: Option<usize>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub directories: DirectoryHandling,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub move_workers: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub move_queue_size: Option<usize>,
+}
+
+impl FsmConfigRepresentation {
+    /**
+    Collision strategy used by filters that do not set their own. `overwrite_on_move` is shorthand for
+    the `overwrite` strategy.
+    */
+    pub fn get_collision_strategy(&self) -> CollisionStrategy {
+        match self.on_collision {
+            Some(res) => res,
+            None if self.overwrite_on_move => CollisionStrategy::Overwrite,
+            None => CollisionStrategy::Rename,
+        }
+    }
+
+    /**
+    All watch roots, with `watch_path` (if set) as the first one.
+    */
+    pub fn get_watch_roots(&self) -> Vec<WatchRootRepresentation> {
+        let mut watch_roots = vec![];
+        if let Some(watch_path) = &self.watch_path {
+            watch_roots.push(WatchRootRepresentation {
+                path: watch_path.clone(),
+                recursive: None,
+                filters: None,
+                managed_path: None,
+                max_depth: None,
+                directories: None,
+                schedule: None,
+            });
+        }
+        watch_roots.extend(self.watch_roots.iter().cloned());
+        watch_roots
+    }
+}
+
+/**
+Formats a config file can be written in, chosen by its extension.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl ConfigFormat {
+    pub fn from_path(file_path: &Path) -> Result<Self, FsmError> {
+        let extension = file_path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_lowercase());
+        match extension.as_deref() {
+            Some("json") => Ok(ConfigFormat::Json),
+            Some("toml") => Ok(ConfigFormat::Toml),
+            Some("yaml") | Some("yml") => Ok(ConfigFormat::Yaml),
+            _ => Err(FsmError::new(
+                ErrorType::ConfigError,
+                format!(
+                    "Cannot tell the format of {:?}, expected a .json, .toml, .yaml or .yml file.",
+                    file_path
+                ),
+            )),
+        }
+    }
+
+    pub fn parse(&self, source: &str) -> Result<FsmConfigRepresentation, FsmError> {
+        match self {
+            ConfigFormat::Json => Ok(serde_json::from_str(source)?),
+            ConfigFormat::Toml => Ok(toml::from_str(source)?),
+            // enums are written as single key maps like in JSON, rather than YAML tags
+            ConfigFormat::Yaml => Ok(serde_saphyr::from_str(source)?),
+        }
+    }
+
+    pub fn write(&self, fsm_config: &FsmConfigRepresentation) -> Result<String, FsmError> {
+        match self {
+            ConfigFormat::Json => Ok(serde_json::to_string_pretty(fsm_config)?),
+            ConfigFormat::Toml => Ok(toml::to_string_pretty(fsm_config)?),
+            ConfigFormat::Yaml => Ok(serde_saphyr::to_string(fsm_config)?),
+        }
+    }
+}
+
+/**
+Reads the config in the format given by the file extension without expanding its paths or validating
+it, for commands such as `fsm undo` that only need a few of its settings.
+*/
+pub fn parse_fsm_config<P>(file_path: P) -> Result<FsmConfigRepresentation, FsmError>
+where
+    P: AsRef<Path>,
+{
+    let format = ConfigFormat::from_path(file_path.as_ref())?;
+    format.parse(&fs::read_to_string(file_path.as_ref())?)
 }
 
+/**
+Reads the config in the format given by the file extension, expanding its paths and validating it.
+*/
 pub fn read_fsm_config<P>(file_path: P) -> Result<FsmConfigRepresentation, FsmError>
 where
     P: AsRef<Path>,
 {
-    let file = fs::File::open(file_path)?;
-    let mut reader = BufReader::new(file);
-    let result: FsmConfigRepresentation = serde_json::from_reader(&mut reader)?;
+    let format = ConfigFormat::from_path(file_path.as_ref())?;
+    let source = fs::read_to_string(file_path.as_ref())?;
+    let mut result = format.parse(&source)?;
+    // every problem is reported at once, rather than one per attempt
+    let mut problems = expand_fsm_config_paths(&mut result, &source, format);
+    let validation_problems: Vec<ConfigProblem> = validate_fsm_config(&result, &source, format)
+        .into_iter()
+        // paths that could not be expanded are only reported once
+        .filter(|problem| {
+            problems
+                .iter()
+                .all(|other| other.pointer != problem.pointer)
+        })
+        .collect();
+    problems.extend(validation_problems);
+    problems.sort_by_key(|problem| (problem.line, problem.column));
+    if !problems.is_empty() {
+        let problems: Vec<String> = problems
+            .iter()
+            .map(|problem| format!("  {}", problem))
+            .collect();
+        return Err(FsmError::new(
+            ErrorType::ConfigError,
+            format!(
+                "Found {} problems in {:?}:\n{}",
+                problems.len(),
+                file_path.as_ref(),
+                problems.join("\n")
+            ),
+        ));
+    }
     Ok(result)
 }
+
+/**
+Rewrites a config file in the format given by the extension of `to_path`. The config is only parsed,
+not validated, so that configs for other machines can be converted as well.
+*/
+pub fn convert_fsm_config<P, Q>(from_path: P, to_path: Q) -> Result<(), FsmError>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let to_format = ConfigFormat::from_path(to_path.as_ref())?;
+    let fsm_config = parse_fsm_config(from_path)?;
+    fs::write(to_path, to_format.write(&fsm_config)?)?;
+    Ok(())
+}
+
+const CONFIG_DEBOUNCE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/**
+Watches the config file for changes until dropped.
+*/
+pub struct ConfigFileWatcher {
+    _debouncer: Debouncer<RecommendedWatcher, FileIdMap>,
+}
+
+/**
+Calls `on_change` whenever the config file is written or replaced.
+*/
+pub fn watch_fsm_config<P, F>(file_path: P, mut on_change: F) -> Result<ConfigFileWatcher, FsmError>
+where
+    P: AsRef<Path>,
+    F: FnMut() + Send + 'static,
+{
+    let file_path = fs::canonicalize(file_path)?;
+    // editors often save by replacing the file, so its directory is watched rather than the file
+    let directory = match file_path.parent() {
+        Some(res) => res.to_path_buf(),
+        None => {
+            return Err(FsmError::new(
+                ErrorType::WatcherError,
+                format!("Cannot watch {:?}, it has no parent directory.", file_path),
+            ))
+        }
+    };
+    let mut debouncer = match new_debouncer(
+        CONFIG_DEBOUNCE_TIMEOUT,
+        None,
+        move |result: DebounceEventResult| {
+            let changed = result.is_ok_and(|events| {
+                events.iter().any(|event| {
+                    !event.kind.is_access() && event.paths.iter().any(|path| path == &file_path)
+                })
+            });
+            if changed {
+                on_change();
+            }
+        },
+    ) {
+        Ok(res) => res,
+        Err(e) => return Err(FsmError::new(ErrorType::WatcherError, e.to_string())),
+    };
+    if let Err(e) = debouncer
+        .watcher()
+        .watch(&directory, RecursiveMode::NonRecursive)
+    {
+        return Err(FsmError::new(ErrorType::WatcherError, e.to_string()));
+    }
+    Ok(ConfigFileWatcher {
+        _debouncer: debouncer,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE_CONFIG: &str = include_str!("../fsm_config_example.json");
+
+    fn to_json(fsm_config: &FsmConfigRepresentation) -> serde_json::Value {
+        serde_json::to_value(fsm_config).unwrap()
+    }
+
+    #[test]
+    fn round_trips_every_format() {
+        let fsm_config = ConfigFormat::Json.parse(EXAMPLE_CONFIG).unwrap();
+        for format in [ConfigFormat::Json, ConfigFormat::Toml, ConfigFormat::Yaml] {
+            let written = format.write(&fsm_config).unwrap();
+            let parsed = format.parse(&written).unwrap();
+            assert_eq!(to_json(&parsed), to_json(&fsm_config), "{:?}", format);
+            // writing is stable, so converted configs only change where the config did
+            assert_eq!(format.write(&parsed).unwrap(), written, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn writes_configs_as_they_were_written() {
+        let source = r#"{
+            "watch_path": "/tmp/watch",
+            "managed_path": "/tmp/managed",
+            "managed_directory_structure": {
+                "zebra": "zebra",
+                "apple": {"pears": "pears", "figs": "figs"},
+                "mango": "mango"
+            },
+            "filters": [
+                {"directory_key": "figs", "date_source": {"filename": "%Y-%m-%d"}},
+                {"directory_key": "mango", "action": "copy", "allowed_filetypes": ["b", "a"]}
+            ]
+        }"#;
+        let fsm_config = ConfigFormat::Json.parse(source).unwrap();
+        let written = ConfigFormat::Yaml.write(&fsm_config).unwrap();
+        let positions: Vec<usize> = ["zebra", "apple", "pears", "figs", "mango"]
+            .iter()
+            .map(|key| written.find(&format!("{}:", key)).unwrap())
+            .collect();
+        assert!(positions.is_sorted(), "{}", written);
+        // settings left at their default are not written out
+        for default in [
+            "action: move",
+            "age_timestamp",
+            "dry_run",
+            "directories",
+            "exclude",
+        ] {
+            assert!(!written.contains(default), "{}", written);
+        }
+        assert!(written.contains("action: copy"), "{}", written);
+
+        let written = ConfigFormat::Json.write(&fsm_config).unwrap();
+        let filters = &serde_json::from_str::<serde_json::Value>(&written).unwrap()["filters"];
+        assert_eq!(
+            filters[0]["date_source"],
+            serde_json::json!({"filename": "%Y-%m-%d"})
+        );
+        assert_eq!(
+            filters[1]["allowed_filetypes"],
+            serde_json::json!(["a", "b"])
+        );
+    }
+
+    #[test]
+    fn tells_formats_apart_by_extension() {
+        let format = |path: &str| ConfigFormat::from_path(Path::new(path));
+        assert_eq!(format("fsm_config.json").unwrap(), ConfigFormat::Json);
+        assert_eq!(format("fsm_config.TOML").unwrap(), ConfigFormat::Toml);
+        assert_eq!(format("fsm_config.yml").unwrap(), ConfigFormat::Yaml);
+        assert!(format("fsm_config").is_err());
+        assert!(format("fsm_config.ini").is_err());
+    }
+}
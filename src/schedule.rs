@@ -0,0 +1,247 @@
+use std::{fmt::Display, hash::Hash, str::FromStr, sync::Arc};
+
+use chrono::{DateTime, Local};
+use cron::Schedule;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{ErrorType, FsmError};
+
+/**
+Calendar schedule for sweeps, written as a cron expression. Both the standard five fields
+(`"0 18 * * Mon-Fri"`, every weekday at 18:00) and the six or seven field form with seconds and
+years are accepted, as are the shortcuts `@hourly`, `@daily`, `@weekly`, `@monthly` (the first of
+the month) and `@yearly`. Numeric days of the week follow standard cron, 0 or 7 for Sunday and 1
+for Monday.
+*/
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(try_from = "String", into = "String")]
+pub struct SweepSchedule {
+    expression: String,
+    // shared, since schedules are cloned into every message of a scheduled sweep
+    schedule: Arc<Schedule>,
+}
+
+impl SweepSchedule {
+    pub fn get_expression(&self) -> &str {
+        &self.expression
+    }
+
+    /**
+    Returns the first time the schedule fires after `after`.
+    */
+    pub fn next_after(&self, after: &DateTime<Local>) -> Option<DateTime<Local>> {
+        self.schedule.after(after).next()
+    }
+}
+
+fn parse_day(day: &str) -> Result<u32, String> {
+    match day.parse::<u32>() {
+        Ok(res) if res <= 7 => Ok(res),
+        _ => Err(format!("invalid day of the week {:?}", day)),
+    }
+}
+
+/**
+Converts a day-of-week field from the standard cron numbering to the cron crate's, which counts from
+1 for Sunday. Numeric ranges and steps are expanded into lists, since a range such as `5-7` wraps
+around, while day names are kept as written.
+*/
+fn convert_days_of_week(field: &str) -> Result<String, String> {
+    let mut parts = vec![];
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => match step.parse::<u32>() {
+                Ok(res) if res > 0 => (range, Some(res)),
+                _ => return Err(format!("invalid step {:?}", step)),
+            },
+            None => (part, None),
+        };
+        let (first, last) = match (range, range.split_once('-')) {
+            ("*", _) if step.is_none() => {
+                parts.push(part.to_string());
+                continue;
+            }
+            ("*", _) => (0, 6),
+            (_, Some((first, last))) if first.parse::<u32>().is_ok() => {
+                (parse_day(first)?, parse_day(last)?)
+            }
+            (day, None) if day.parse::<u32>().is_ok() => {
+                let day = parse_day(day)?;
+                (day, if step.is_some() { 7 } else { day })
+            }
+            // day names
+            _ => {
+                parts.push(part.to_string());
+                continue;
+            }
+        };
+        if first > last {
+            return Err(format!("invalid range of days {:?}", range));
+        }
+        for day in (first..=last).step_by(step.unwrap_or(1) as usize) {
+            parts.push((day % 7 + 1).to_string());
+        }
+    }
+    parts.dedup();
+    Ok(parts.join(","))
+}
+
+/**
+Converts a cron expression into the form expected by the cron crate, with a leading seconds field
+and its day-of-week numbering.
+*/
+fn normalize_expression(expression: &str) -> Result<String, String> {
+    let mut fields: Vec<String> = expression.split_whitespace().map(String::from).collect();
+    // the cron crate expects a leading seconds field
+    if fields.len() == 5 {
+        fields.insert(0, "0".to_string());
+    }
+    if let Some(days_of_week) = fields.get_mut(5) {
+        *days_of_week = convert_days_of_week(days_of_week)?;
+    }
+    Ok(fields.join(" "))
+}
+
+impl FromStr for SweepSchedule {
+    type Err = FsmError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let expression = s.trim();
+        let invalid_schedule = |e: &dyn Display| {
+            FsmError::new(
+                ErrorType::ConfigError,
+                format!("Invalid schedule {:?} provided: {}", s, e),
+            )
+        };
+        let normalized = match expression.starts_with('@') {
+            true => expression.to_string(),
+            false => normalize_expression(expression).map_err(|e| invalid_schedule(&e))?,
+        };
+        match Schedule::from_str(&normalized) {
+            Ok(schedule) => Ok(Self {
+                expression: expression.to_string(),
+                schedule: Arc::new(schedule),
+            }),
+            Err(e) => Err(invalid_schedule(&e)),
+        }
+    }
+}
+
+impl TryFrom<String> for SweepSchedule {
+    type Error = FsmError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<SweepSchedule> for String {
+    fn from(schedule: SweepSchedule) -> Self {
+        schedule.expression
+    }
+}
+
+impl Display for SweepSchedule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.expression)
+    }
+}
+
+// schedules are told apart by how they were written
+impl PartialEq for SweepSchedule {
+    fn eq(&self, other: &Self) -> bool {
+        self.expression == other.expression
+    }
+}
+
+impl Eq for SweepSchedule {}
+
+impl Hash for SweepSchedule {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.expression.hash(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Datelike, TimeZone, Timelike, Weekday};
+
+    fn next_runs(expression: &str, count: usize) -> Vec<DateTime<Local>> {
+        let schedule: SweepSchedule = expression.parse().unwrap();
+        // a Sunday
+        let mut after = Local.with_ymd_and_hms(2024, 6, 2, 12, 0, 0).unwrap();
+        let mut runs = vec![];
+        for _ in 0..count {
+            after = schedule.next_after(&after).unwrap();
+            runs.push(after);
+        }
+        runs
+    }
+
+    fn weekdays(expression: &str, count: usize) -> Vec<Weekday> {
+        next_runs(expression, count)
+            .iter()
+            .map(|run| run.weekday())
+            .collect()
+    }
+
+    #[test]
+    fn uses_standard_day_numbers() {
+        use Weekday::*;
+        assert_eq!(weekdays("0 18 * * 1-5", 5), [Mon, Tue, Wed, Thu, Fri]);
+        assert_eq!(weekdays("0 18 * * Mon-Fri", 5), [Mon, Tue, Wed, Thu, Fri]);
+        assert_eq!(weekdays("0 9 * * 0", 2), [Sun, Sun]);
+        assert_eq!(weekdays("0 9 * * 7", 2), [Sun, Sun]);
+        assert_eq!(weekdays("0 9 * * 5-7", 3), [Fri, Sat, Sun]);
+        assert_eq!(weekdays("0 9 * * 0,6", 2), [Sat, Sun]);
+        assert_eq!(weekdays("0 9 * * */2", 4), [Tue, Thu, Sat, Sun]);
+        assert_eq!(weekdays("0 9 * * 0-7", 7).len(), 7);
+        // the six field form with seconds numbers days the same way
+        assert_eq!(weekdays("30 0 18 * * 1", 1), [Mon]);
+    }
+
+    #[test]
+    fn fires_at_the_given_time() {
+        let run = next_runs("15 18 * * *", 1)[0];
+        assert_eq!(
+            (run.day(), run.hour(), run.minute(), run.second()),
+            (2, 18, 15, 0)
+        );
+        let run = next_runs("@daily", 1)[0];
+        assert_eq!((run.day(), run.hour()), (3, 0));
+        let run = next_runs("@monthly", 1)[0];
+        assert_eq!((run.month(), run.day()), (7, 1));
+    }
+
+    #[test]
+    fn rejects_invalid_schedules() {
+        for expression in [
+            "",
+            "* * *",
+            "0 18 * * 8",
+            "0 18 * * 5-1",
+            "0 18 * * */0",
+            "0 61 * * *",
+        ] {
+            let error = expression.parse::<SweepSchedule>().unwrap_err();
+            assert!(
+                matches!(error.get_error_type(), ErrorType::ConfigError),
+                "{}",
+                expression
+            );
+        }
+    }
+
+    #[test]
+    fn compares_by_expression() {
+        let schedule: SweepSchedule = " 0 18 * * 1-5 ".parse().unwrap();
+        assert_eq!(schedule.get_expression(), "0 18 * * 1-5");
+        assert_eq!(schedule, "0 18 * * 1-5".parse().unwrap());
+        assert_ne!(schedule, "0 18 * * Mon-Fri".parse().unwrap());
+        assert_eq!(
+            serde_json::to_string(&schedule).unwrap(),
+            "\"0 18 * * 1-5\""
+        );
+    }
+}
@@ -0,0 +1,188 @@
+use std::{
+    fs,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+/**
+Number of stability windows to wait for before giving up on a file that keeps changing. Files that
+are given up on are picked up again by a later sweep.
+*/
+const MAX_STABILITY_CHECKS: u32 = 10;
+
+pub const DEFAULT_TEMPORARY_SUFFIXES: [&str; 3] = [".part", ".crdownload", ".tmp"];
+
+/**
+Whether the file name ends with one of the given temporary suffixes (e.g. a partial download).
+*/
+pub fn has_temporary_suffix(path: &Path, temporary_suffixes: &[String]) -> bool {
+    let filename = path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_lowercase();
+    temporary_suffixes
+        .iter()
+        .any(|suffix| filename.ends_with(&suffix.to_lowercase()))
+}
+
+/**
+Size and modification time of a file at its last stability check, along with how many checks it has
+had.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StabilityCheck {
+    snapshot: (u64, SystemTime),
+    checks: u32,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Stability {
+    Stable,
+    // check again once the window has passed
+    Changing(StabilityCheck),
+    Missing,
+    GaveUp,
+}
+
+fn get_snapshot(path: &Path) -> Option<(u64, SystemTime)> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.len(), metadata.modified().ok()?))
+}
+
+/**
+Checks whether the size and modification time of a file stayed unchanged for `window` since the
+previous check, and no process has it open for writing. Files that have not been modified for a
+whole window already are stable on the first check. Never waits, a changing file is checked again
+by the caller once the window has passed.
+*/
+pub fn check_stability(
+    path: &Path,
+    window: Duration,
+    previous: Option<StabilityCheck>,
+) -> Stability {
+    if path.is_dir() || window.is_zero() {
+        return match path.exists() {
+            true => Stability::Stable,
+            false => Stability::Missing,
+        };
+    }
+    let current = match get_snapshot(path) {
+        Some(res) => res,
+        None => return Stability::Missing,
+    };
+    let settled = match previous {
+        Some(previous) => previous.snapshot == current,
+        None => current
+            .1
+            .elapsed()
+            .is_ok_and(|since_modified| since_modified >= window),
+    };
+    // the scan of open files is only done once the file looks settled
+    if settled && !is_open_for_writing(path) {
+        return Stability::Stable;
+    }
+    let checks = previous.map_or(0, |previous| previous.checks + 1);
+    if checks >= MAX_STABILITY_CHECKS {
+        return Stability::GaveUp;
+    }
+    Stability::Changing(StabilityCheck {
+        snapshot: current,
+        checks,
+    })
+}
+
+/**
+Checks whether any process visible to us has the file open for writing, by scanning the open file
+descriptors in `/proc`.
+*/
+#[cfg(target_os = "linux")]
+pub fn is_open_for_writing(path: &Path) -> bool {
+    const O_ACCMODE: u32 = 0o3;
+    let path = match fs::canonicalize(path) {
+        Ok(res) => res,
+        Err(_) => return false,
+    };
+    let processes = match fs::read_dir("/proc") {
+        Ok(res) => res,
+        Err(_) => return false,
+    };
+    for process in processes.flatten() {
+        let process_path = process.path();
+        let is_pid = process
+            .file_name()
+            .to_str()
+            .is_some_and(|name| name.chars().all(|c| c.is_ascii_digit()));
+        if !is_pid {
+            continue;
+        }
+        let descriptors = match fs::read_dir(process_path.join("fd")) {
+            Ok(res) => res,
+            Err(_) => continue,
+        };
+        for descriptor in descriptors.flatten() {
+            if fs::read_link(descriptor.path()).ok().as_ref() != Some(&path) {
+                continue;
+            }
+            let fdinfo = process_path.join("fdinfo").join(descriptor.file_name());
+            let flags = fs::read_to_string(fdinfo).ok().and_then(|info| {
+                info.lines()
+                    .find_map(|line| line.strip_prefix("flags:"))
+                    .and_then(|flags| u32::from_str_radix(flags.trim(), 8).ok())
+            });
+            if flags.is_some_and(|flags| flags & O_ACCMODE != 0) {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn is_open_for_writing(_path: &Path) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn checks_again_until_settled() {
+        let file = env::temp_dir().join(format!("fsm_stability_{}.txt", std::process::id()));
+        let window = Duration::from_secs(60);
+        assert_eq!(check_stability(&file, window, None), Stability::Missing);
+
+        // just written, so not settled on the first check
+        fs::write(&file, "partial").unwrap();
+        let first = match check_stability(&file, window, None) {
+            Stability::Changing(res) => res,
+            other => panic!("{:?}", other),
+        };
+        fs::write(&file, "partial, and then some").unwrap();
+        let second = match check_stability(&file, window, Some(first)) {
+            Stability::Changing(res) => res,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(second.checks, 1);
+        assert_eq!(
+            check_stability(&file, window, Some(second)),
+            Stability::Stable
+        );
+
+        let gave_up = StabilityCheck {
+            snapshot: (0, SystemTime::UNIX_EPOCH),
+            checks: MAX_STABILITY_CHECKS - 1,
+        };
+        assert_eq!(
+            check_stability(&file, window, Some(gave_up)),
+            Stability::GaveUp
+        );
+        assert_eq!(
+            check_stability(&file, Duration::ZERO, None),
+            Stability::Stable
+        );
+        fs::remove_file(&file).unwrap();
+    }
+}
@@ -0,0 +1,229 @@
+use std::{
+    ffi::OsString,
+    fs::{self, File, FileTimes},
+    io::{self, BufReader, ErrorKind, Read},
+    path::{Path, PathBuf},
+};
+
+use log::warn;
+
+const VERIFY_BUFFER_SIZE: usize = 64 * 1024;
+
+/**
+Renames a file, falling back to a copy when the source and destination are on different devices.
+*/
+pub fn rename_file(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(e) if e.kind() == ErrorKind::CrossesDevices => move_across_devices(from, to),
+        result => result,
+    }
+}
+
+/**
+Moves a file to another device by copying it next to its destination, syncing and verifying the
+copy, renaming it into place and only then removing the original. A crash partway through leaves at
+most a temporary file behind, never a partially written destination. Once the copy is in place the
+move succeeds, even if the original cannot be removed; it is then left behind with a warning.
+*/
+pub fn move_across_devices(from: &Path, to: &Path) -> io::Result<()> {
+    let metadata = fs::metadata(from)?;
+    if metadata.is_dir() {
+        return Err(io::Error::new(
+            ErrorKind::CrossesDevices,
+            format!("Cannot move directory {:?} across devices.", from),
+        ));
+    }
+    let temp_path = get_temp_path(to);
+    let result = copy_verified(from, &temp_path, &metadata).and_then(|_| {
+        fs::rename(&temp_path, to)?;
+        sync_parent(to)
+    });
+    if let Err(e) = result {
+        let _ = fs::remove_file(&temp_path);
+        return Err(e);
+    }
+    if let Err(e) = fs::remove_file(from) {
+        warn!(
+            "Copied {:?} to {:?}, but could not remove the original: {}",
+            from, to, e
+        );
+    }
+    Ok(())
+}
+
+fn get_temp_path(to: &Path) -> PathBuf {
+    let mut temp_name = OsString::from(".");
+    temp_name.push(to.file_name().unwrap_or_default());
+    temp_name.push(".fsm-partial");
+    to.with_file_name(temp_name)
+}
+
+fn copy_verified(from: &Path, to: &Path, metadata: &fs::Metadata) -> io::Result<()> {
+    // fs::copy also carries over the permissions of the original
+    fs::copy(from, to)?;
+    let copy = File::options().write(true).open(to)?;
+    let mut times = FileTimes::new();
+    if let Ok(accessed) = metadata.accessed() {
+        times = times.set_accessed(accessed);
+    }
+    if let Ok(modified) = metadata.modified() {
+        times = times.set_modified(modified);
+    }
+    copy.set_times(times)?;
+    copy.sync_all()?;
+    drop(copy);
+
+    if !contents_equal(from, to)? {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("Copy of {:?} does not match the original.", from),
+        ));
+    }
+    Ok(())
+}
+
+fn contents_equal(first: &Path, second: &Path) -> io::Result<bool> {
+    if fs::metadata(first)?.len() != fs::metadata(second)?.len() {
+        return Ok(false);
+    }
+    let mut first = BufReader::new(File::open(first)?);
+    let mut second = BufReader::new(File::open(second)?);
+    let mut first_buffer = vec![0; VERIFY_BUFFER_SIZE];
+    let mut second_buffer = vec![0; VERIFY_BUFFER_SIZE];
+    loop {
+        let read = read_full(&mut first, &mut first_buffer)?;
+        if read != read_full(&mut second, &mut second_buffer)? {
+            return Ok(false);
+        }
+        if read == 0 {
+            return Ok(true);
+        }
+        if first_buffer[..read] != second_buffer[..read] {
+            return Ok(false);
+        }
+    }
+}
+
+fn read_full(reader: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut total = 0;
+    while total < buffer.len() {
+        match reader.read(&mut buffer[total..]) {
+            Ok(0) => break,
+            Ok(read) => total += read,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(total)
+}
+
+#[cfg(unix)]
+fn sync_parent(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(parent) => File::open(parent)?.sync_all(),
+        None => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_parent(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::time::{Duration, SystemTime};
+
+    fn test_directory(name: &str) -> PathBuf {
+        let directory =
+            env::temp_dir().join(format!("fsm_transfer_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    #[test]
+    fn moves_verified_copies_across_devices() {
+        let directory = test_directory("move");
+        let from = directory.join("archive.bin");
+        let to = directory.join("moved.bin");
+        // spans several verification buffers
+        let contents: Vec<u8> = (0..3 * VERIFY_BUFFER_SIZE + 17)
+            .map(|index| (index % 251) as u8)
+            .collect();
+        fs::write(&from, &contents).unwrap();
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        File::options()
+            .write(true)
+            .open(&from)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+
+        move_across_devices(&from, &to).unwrap();
+        assert!(!from.exists());
+        assert_eq!(fs::read(&to).unwrap(), contents);
+        assert_eq!(fs::metadata(&to).unwrap().modified().unwrap(), modified);
+        assert!(!get_temp_path(&to).exists());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn keeps_the_original_when_the_rename_fails() {
+        let directory = test_directory("rename");
+        let from = directory.join("report.pdf");
+        fs::write(&from, "report").unwrap();
+        // a file cannot be renamed over a directory that is not empty
+        let to = directory.join("taken");
+        fs::create_dir_all(to.join("inside")).unwrap();
+
+        assert!(move_across_devices(&from, &to).is_err());
+        assert_eq!(fs::read_to_string(&from).unwrap(), "report");
+        assert!(to.join("inside").is_dir());
+        assert!(!get_temp_path(&to).exists());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn removes_copies_that_fail_verification() {
+        let directory = test_directory("verify");
+        // reported as empty but has contents, so the copy never matches the original
+        let from = Path::new("/proc/self/stat");
+        let to = directory.join("stat");
+
+        let error = move_across_devices(from, &to).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert!(!to.exists());
+        assert!(!get_temp_path(&to).exists());
+        assert!(from.exists());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn refuses_to_move_directories() {
+        let directory = test_directory("directory");
+        let from = directory.join("folder");
+        fs::create_dir_all(&from).unwrap();
+        assert!(move_across_devices(&from, &directory.join("moved")).is_err());
+        assert!(from.is_dir());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn compares_contents() {
+        let directory = test_directory("compare");
+        let first = directory.join("first");
+        let second = directory.join("second");
+        fs::write(&first, "same length").unwrap();
+        fs::write(&second, "same length").unwrap();
+        assert!(contents_equal(&first, &second).unwrap());
+        fs::write(&second, "same lenGth").unwrap();
+        assert!(!contents_equal(&first, &second).unwrap());
+        fs::write(&second, "longer contents").unwrap();
+        assert!(!contents_equal(&first, &second).unwrap());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,806 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use regex::Regex;
+use saphyr_parser::{Event, Parser, Span, SpannedEventReceiver};
+use toml_edit::{ImDocument, Item, Table, Value};
+
+use crate::{
+    expand::expand_path,
+    filter::{FileFilter, FilterCondition},
+    reader::{ConfigFormat, DirectoryEntry, FsmConfigRepresentation},
+};
+
+/**
+Problem found while validating a config file. `pointer` is a JSON pointer to the offending value
+(e.g. `/filters/2/directory_key`), and `line` and `column` give where that value starts in the file.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigProblem {
+    pub pointer: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl Display for ConfigProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} (line {}, column {}): {}",
+            self.pointer, self.line, self.column, self.message
+        )
+    }
+}
+
+fn skip_whitespace(bytes: &[u8], mut pos: usize) -> usize {
+    while bytes
+        .get(pos)
+        .is_some_and(|byte| byte.is_ascii_whitespace())
+    {
+        pos += 1;
+    }
+    pos
+}
+
+/**
+Returns the position just after the string starting at `pos`.
+*/
+fn skip_string(bytes: &[u8], mut pos: usize) -> usize {
+    pos += 1;
+    while let Some(byte) = bytes.get(pos) {
+        match byte {
+            b'\\' => pos += 2,
+            b'"' => return pos + 1,
+            _ => pos += 1,
+        }
+    }
+    pos
+}
+
+/**
+Returns the position just after the value starting at `pos`.
+*/
+fn skip_value(bytes: &[u8], mut pos: usize) -> usize {
+    let mut depth = 0;
+    while let Some(byte) = bytes.get(pos) {
+        match byte {
+            b'"' => {
+                pos = skip_string(bytes, pos);
+                if depth == 0 {
+                    return pos;
+                }
+                continue;
+            }
+            b'{' | b'[' => depth += 1,
+            b'}' | b']' if depth == 0 => return pos,
+            b'}' | b']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return pos + 1;
+                }
+            }
+            b',' if depth == 0 => return pos,
+            byte if byte.is_ascii_whitespace() && depth == 0 => return pos,
+            _ => (),
+        }
+        pos += 1;
+    }
+    pos
+}
+
+/**
+Follows the pointer tokens from the value starting at `pos`, returning the position of the value
+they lead to, or of the last value found along the way.
+*/
+fn find_value(source: &str, pos: usize, tokens: &[String]) -> usize {
+    let bytes = source.as_bytes();
+    let token = match tokens.first() {
+        Some(res) => res,
+        None => return pos,
+    };
+    let mut curr = pos + 1;
+    match bytes.get(pos) {
+        Some(b'{') => loop {
+            curr = skip_whitespace(bytes, curr);
+            if bytes.get(curr) != Some(&b'"') {
+                return pos;
+            }
+            let key_end = skip_string(bytes, curr);
+            let key = source
+                .get(curr..key_end)
+                .and_then(|key| serde_json::from_str::<String>(key).ok());
+            curr = skip_whitespace(bytes, key_end);
+            if bytes.get(curr) != Some(&b':') {
+                return pos;
+            }
+            curr = skip_whitespace(bytes, curr + 1);
+            if key.as_ref() == Some(token) {
+                return find_value(source, curr, &tokens[1..]);
+            }
+            curr = skip_whitespace(bytes, skip_value(bytes, curr));
+            if bytes.get(curr) != Some(&b',') {
+                return pos;
+            }
+            curr += 1;
+        },
+        Some(b'[') => {
+            let index = match token.parse::<usize>() {
+                Ok(res) => res,
+                Err(_) => return pos,
+            };
+            for _ in 0..index {
+                curr = skip_whitespace(bytes, curr);
+                curr = skip_whitespace(bytes, skip_value(bytes, curr));
+                if bytes.get(curr) != Some(&b',') {
+                    return pos;
+                }
+                curr += 1;
+            }
+            curr = skip_whitespace(bytes, curr);
+            match bytes.get(curr) {
+                None | Some(b']') => pos,
+                Some(_) => find_value(source, curr, &tokens[1..]),
+            }
+        }
+        _ => pos,
+    }
+}
+
+/**
+Returns the byte offset in the source of the value the JSON pointer refers to. Pointers to values
+missing from the source resolve to the closest parent that is there.
+*/
+fn locate(source: &str, pointer: &str) -> usize {
+    let tokens: Vec<String> = pointer
+        .split('/')
+        .skip(1)
+        .map(|token| token.replace("~1", "/").replace("~0", "~"))
+        .collect();
+    find_value(source, skip_whitespace(source.as_bytes(), 0), &tokens)
+}
+
+fn escape_token(token: &str) -> String {
+    token.replace('~', "~0").replace('/', "~1")
+}
+
+/**
+Line and column, both counted from 1, of a byte offset in the source.
+*/
+fn get_position(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset.min(source.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before
+        .rsplit('\n')
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .count()
+        + 1;
+    (line, column)
+}
+
+/**
+Line and column of the values in a source, keyed by JSON pointer. Values in a table or map are
+located by their key.
+*/
+type PositionIndex = HashMap<String, (usize, usize)>;
+
+fn index_toml_table(table: &Table, pointer: &str, source: &str, index: &mut PositionIndex) {
+    for (key, item) in table.iter() {
+        let child_pointer = format!("{}/{}", pointer, escape_token(key));
+        let span = table
+            .get_key_value(key)
+            .and_then(|(key, _)| key.span())
+            .or_else(|| item.span());
+        if let Some(span) = span {
+            index.insert(child_pointer.clone(), get_position(source, span.start));
+        }
+        index_toml_item(item, &child_pointer, source, index);
+    }
+}
+
+fn index_toml_item(item: &Item, pointer: &str, source: &str, index: &mut PositionIndex) {
+    match item {
+        Item::Table(table) => index_toml_table(table, pointer, source, index),
+        Item::ArrayOfTables(tables) => {
+            for (position, table) in tables.iter().enumerate() {
+                let child_pointer = format!("{}/{}", pointer, position);
+                if let Some(span) = table.span() {
+                    index.insert(child_pointer.clone(), get_position(source, span.start));
+                }
+                index_toml_table(table, &child_pointer, source, index);
+            }
+        }
+        Item::Value(value) => index_toml_value(value, pointer, source, index),
+        Item::None => (),
+    }
+}
+
+fn index_toml_value(value: &Value, pointer: &str, source: &str, index: &mut PositionIndex) {
+    match value {
+        Value::Array(values) => {
+            for (position, value) in values.iter().enumerate() {
+                let child_pointer = format!("{}/{}", pointer, position);
+                if let Some(span) = value.span() {
+                    index.insert(child_pointer.clone(), get_position(source, span.start));
+                }
+                index_toml_value(value, &child_pointer, source, index);
+            }
+        }
+        Value::InlineTable(table) => {
+            for (key, value) in table.iter() {
+                let child_pointer = format!("{}/{}", pointer, escape_token(key));
+                let span = table
+                    .get_key_value(key)
+                    .and_then(|(key, _)| key.span())
+                    .or_else(|| value.span());
+                if let Some(span) = span {
+                    index.insert(child_pointer.clone(), get_position(source, span.start));
+                }
+                index_toml_value(value, &child_pointer, source, index);
+            }
+        }
+        _ => (),
+    }
+}
+
+/**
+Map or sequence the YAML parser is inside of.
+*/
+enum YamlFrame {
+    // key is set while waiting for its value
+    Mapping {
+        pointer: String,
+        key: Option<String>,
+    },
+    Sequence {
+        pointer: String,
+        next_index: usize,
+    },
+}
+
+impl YamlFrame {
+    fn get_pointer(&self) -> &str {
+        match self {
+            YamlFrame::Mapping { pointer, .. } => pointer,
+            YamlFrame::Sequence { pointer, .. } => pointer,
+        }
+    }
+}
+
+#[derive(Default)]
+struct YamlIndexer {
+    index: PositionIndex,
+    stack: Vec<YamlFrame>,
+}
+
+impl<'input> SpannedEventReceiver<'input> for YamlIndexer {
+    fn on_event(&mut self, event: Event<'input>, span: Span) {
+        // lines are counted from 1, columns from 0
+        let position = (span.start.line(), span.start.col() + 1);
+        if matches!(
+            event,
+            Event::Scalar(..)
+                | Event::SequenceStart(..)
+                | Event::MappingStart(..)
+                | Event::Alias(_)
+        ) {
+            // block maps and sequences are only marked after their first entry, so they are located by it
+            if let Some(frame) = self.stack.last() {
+                self.index
+                    .entry(frame.get_pointer().to_string())
+                    .or_insert(position);
+            }
+            // pointer of the node that starts here, unless it is a key
+            let pointer = match self.stack.last_mut() {
+                None => Some(String::new()),
+                Some(YamlFrame::Mapping { pointer, key }) => match key.take() {
+                    Some(key) => Some(format!("{}/{}", pointer, escape_token(&key))),
+                    None => {
+                        let name = match &event {
+                            Event::Scalar(name, ..) => name.to_string(),
+                            _ => String::new(),
+                        };
+                        self.index
+                            .entry(format!("{}/{}", pointer, escape_token(&name)))
+                            .or_insert(position);
+                        *key = Some(name);
+                        None
+                    }
+                },
+                Some(YamlFrame::Sequence {
+                    pointer,
+                    next_index,
+                }) => {
+                    *next_index += 1;
+                    Some(format!("{}/{}", pointer, *next_index - 1))
+                }
+            };
+            if let Some(pointer) = pointer {
+                match event {
+                    Event::SequenceStart(..) => self.stack.push(YamlFrame::Sequence {
+                        pointer,
+                        next_index: 0,
+                    }),
+                    Event::MappingStart(..) => {
+                        self.stack.push(YamlFrame::Mapping { pointer, key: None })
+                    }
+                    _ => {
+                        self.index.entry(pointer).or_insert(position);
+                    }
+                }
+            }
+        }
+        if matches!(event, Event::SequenceEnd | Event::MappingEnd) {
+            self.stack.pop();
+        }
+    }
+}
+
+/**
+Finds the line and column of the value a JSON pointer refers to. JSON is scanned directly, while TOML
+and YAML are indexed up front through their parsers.
+*/
+enum Locator<'a> {
+    Json(&'a str),
+    Indexed(PositionIndex),
+}
+
+impl<'a> Locator<'a> {
+    fn new(source: &'a str, format: ConfigFormat) -> Self {
+        match format {
+            ConfigFormat::Json => Locator::Json(source),
+            ConfigFormat::Toml => {
+                let mut index = PositionIndex::new();
+                if let Ok(document) = ImDocument::parse(source) {
+                    index_toml_table(document.as_table(), "", source, &mut index);
+                }
+                Locator::Indexed(index)
+            }
+            ConfigFormat::Yaml => {
+                let mut indexer = YamlIndexer::default();
+                let _ = Parser::new_from_str(source).load(&mut indexer, false);
+                Locator::Indexed(indexer.index)
+            }
+        }
+    }
+
+    /**
+    Pointers to values missing from the source resolve to the closest parent that is there.
+    */
+    fn get_position(&self, pointer: &str) -> (usize, usize) {
+        match self {
+            Locator::Json(source) => get_position(source, locate(source, pointer)),
+            Locator::Indexed(index) => {
+                let mut pointer = pointer;
+                loop {
+                    if let Some(position) = index.get(pointer) {
+                        return *position;
+                    }
+                    match pointer.rfind('/') {
+                        Some(end) => pointer = &pointer[..end],
+                        None => return (1, 1),
+                    }
+                }
+            }
+        }
+    }
+}
+
+/**
+Problems found so far, along with where to locate them.
+*/
+struct Problems<'a> {
+    locator: Locator<'a>,
+    problems: Vec<ConfigProblem>,
+}
+
+impl Problems<'_> {
+    fn add(&mut self, pointer: String, message: String) {
+        let (line, column) = self.locator.get_position(&pointer);
+        self.problems.push(ConfigProblem {
+            pointer,
+            line,
+            column,
+            message,
+        });
+    }
+}
+
+/**
+Collects the leaf keys of the directory structure along with their pointers.
+*/
+fn collect_leaf_keys<'a>(
+    entry: &'a DirectoryEntry,
+    pointer: String,
+    leaf_keys: &mut Vec<(&'a str, String)>,
+) {
+    match entry {
+        DirectoryEntry::LeafDirectory(key) => leaf_keys.push((key, pointer)),
+        DirectoryEntry::ParentDirectory(children) => {
+            for (name, child) in children.iter() {
+                collect_leaf_keys(
+                    child,
+                    format!("{}/{}", pointer, escape_token(name)),
+                    leaf_keys,
+                );
+            }
+        }
+    }
+}
+
+fn check_pattern(pattern: &str, pointer: String, problems: &mut Problems) {
+    if let Err(e) = Regex::new(pattern) {
+        problems.add(pointer, format!("Invalid filename pattern: {}", e));
+    }
+}
+
+fn check_condition(condition: &FilterCondition, pointer: String, problems: &mut Problems) {
+    match condition {
+        FilterCondition::All(conditions) => {
+            for (index, condition) in conditions.iter().enumerate() {
+                check_condition(condition, format!("{}/all/{}", pointer, index), problems);
+            }
+        }
+        FilterCondition::Any(conditions) => {
+            for (index, condition) in conditions.iter().enumerate() {
+                check_condition(condition, format!("{}/any/{}", pointer, index), problems);
+            }
+        }
+        FilterCondition::Not(condition) => {
+            check_condition(condition, format!("{}/not", pointer), problems)
+        }
+        FilterCondition::FilenamePattern(pattern) => check_pattern(
+            pattern.as_str(),
+            format!("{}/filename_pattern", pointer),
+            problems,
+        ),
+        _ => (),
+    }
+}
+
+fn check_filters(
+    filters: &[FileFilter],
+    pointer: &str,
+    leaf_keys: &HashMap<&str, String>,
+    problems: &mut Problems,
+) {
+    let mut catch_all = None;
+    for (index, filter) in filters.iter().enumerate() {
+        let filter_pointer = format!("{}/{}", pointer, index);
+        if !leaf_keys.contains_key(filter.get_directory_key()) {
+            problems.add(
+                format!("{}/directory_key", filter_pointer),
+                format!(
+                    "The directory key {:?} is not a leaf of managed_directory_structure.",
+                    filter.get_directory_key()
+                ),
+            );
+        }
+        if let Some(pattern) = filter.get_filename_pattern() {
+            check_pattern(
+                pattern,
+                format!("{}/filename_pattern", filter_pointer),
+                problems,
+            );
+        }
+        if let Some(condition) = filter.get_condition() {
+            check_condition(condition, format!("{}/condition", filter_pointer), problems);
+        }
+        match catch_all {
+            Some(catch_all) => problems.add(
+                filter_pointer,
+                format!(
+                    "The filter is unreachable, every file is matched by the catch-all filter {}/{} first.",
+                    pointer, catch_all
+                ),
+            ),
+            None if filter.is_catch_all() => catch_all = Some(index),
+            None => (),
+        }
+    }
+}
+
+/**
+Returns the canonical form of the directory, if it exists.
+*/
+fn check_directory(path: &str, pointer: String, problems: &mut Problems) -> Option<PathBuf> {
+    match fs::canonicalize(path) {
+        Ok(res) if res.is_dir() => Some(res),
+        Ok(_) => {
+            problems.add(pointer, format!("{:?} is not a directory.", path));
+            None
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            problems.add(pointer, format!("{:?} does not exist.", path));
+            None
+        }
+        Err(e) => {
+            problems.add(pointer, format!("{:?} cannot be read: {}", path, e));
+            None
+        }
+    }
+}
+
+fn overlaps(first: &Path, second: &Path) -> bool {
+    first.starts_with(second) || second.starts_with(first)
+}
+
+/**
+Expands the watch, managed and journal paths of the config (see `expand_path`), returning the paths
+that could not be expanded as problems. `source` and `format` are used to locate them, as for
+`validate_fsm_config`.
+*/
+pub fn expand_fsm_config_paths(
+    config: &mut FsmConfigRepresentation,
+    source: &str,
+    format: ConfigFormat,
+) -> Vec<ConfigProblem> {
+    let mut problems = Problems {
+        locator: Locator::new(source, format),
+        problems: vec![],
+    };
+    let mut paths = vec![("/managed_path".to_string(), &mut config.managed_path)];
+    if let Some(watch_path) = config.watch_path.as_mut() {
+        paths.push(("/watch_path".to_string(), watch_path));
+    }
+    if let Some(journal_path) = config.journal_path.as_mut() {
+        paths.push(("/journal_path".to_string(), journal_path));
+    }
+    for (index, watch_root) in config.watch_roots.iter_mut().enumerate() {
+        paths.push((format!("/watch_roots/{}/path", index), &mut watch_root.path));
+        if let Some(managed_path) = watch_root.managed_path.as_mut() {
+            paths.push((format!("/watch_roots/{}/managed_path", index), managed_path));
+        }
+    }
+    for (pointer, path) in paths {
+        match expand_path(path) {
+            Ok(res) => *path = res,
+            Err(e) => problems.add(pointer, e.get_error_message().to_string()),
+        }
+    }
+    problems.problems
+}
+
+/**
+Checks the parsed config for problems that deserializing it cannot catch: directory keys that are not
+leaves of the managed directory structure, leaf keys used twice, filename patterns that do not
+compile, filters that can never match because of a catch-all before them, and watch and managed
+paths that are missing or inside one another. `source` is the text the config was read from, in the
+given format, used to locate the problems.
+*/
+pub fn validate_fsm_config(
+    config: &FsmConfigRepresentation,
+    source: &str,
+    format: ConfigFormat,
+) -> Vec<ConfigProblem> {
+    let mut problems = Problems {
+        locator: Locator::new(source, format),
+        problems: vec![],
+    };
+
+    if config.watch_path.is_none() && config.watch_roots.is_empty() {
+        problems.add(
+            String::new(),
+            "Either watch_path or watch_roots must be provided.".to_string(),
+        );
+    }
+
+    let mut leaf_keys = vec![];
+    collect_leaf_keys(
+        &config.managed_directory_structure,
+        "/managed_directory_structure".to_string(),
+        &mut leaf_keys,
+    );
+    // the structure is read into a map, so duplicates are reported in the order they appear in
+    leaf_keys.sort_by_key(|(_, pointer)| problems.locator.get_position(pointer));
+    let mut unique_keys: HashMap<&str, String> = HashMap::new();
+    for (key, pointer) in leaf_keys {
+        match unique_keys.get(key) {
+            Some(first) => {
+                let message = format!("The directory key {:?} is already used by {}.", key, first);
+                problems.add(pointer, message);
+            }
+            None => {
+                unique_keys.insert(key, pointer);
+            }
+        }
+    }
+
+    check_filters(&config.filters, "/filters", &unique_keys, &mut problems);
+
+    let mut managed_paths = vec![];
+    let mut watch_paths = vec![];
+    if let Some(managed_path) = check_directory(
+        &config.managed_path,
+        "/managed_path".to_string(),
+        &mut problems,
+    ) {
+        managed_paths.push(managed_path);
+    }
+    if let Some(watch_path) = &config.watch_path {
+        let pointer = "/watch_path".to_string();
+        if let Some(path) = check_directory(watch_path, pointer.clone(), &mut problems) {
+            watch_paths.push((path, pointer));
+        }
+    }
+    for (index, watch_root) in config.watch_roots.iter().enumerate() {
+        let pointer = format!("/watch_roots/{}", index);
+        if let Some(path) =
+            check_directory(&watch_root.path, format!("{}/path", pointer), &mut problems)
+        {
+            watch_paths.push((path, format!("{}/path", pointer)));
+        }
+        if let Some(managed_path) = &watch_root.managed_path {
+            if let Some(path) = check_directory(
+                managed_path,
+                format!("{}/managed_path", pointer),
+                &mut problems,
+            ) {
+                managed_paths.push(path);
+            }
+        }
+        if let Some(filters) = &watch_root.filters {
+            check_filters(
+                filters,
+                &format!("{}/filters", pointer),
+                &unique_keys,
+                &mut problems,
+            );
+        }
+    }
+    // fsm would otherwise pick up the files it just moved, or move its own managed directories
+    for (watch_path, pointer) in watch_paths {
+        for managed_path in managed_paths.iter() {
+            if overlaps(&watch_path, managed_path) {
+                problems.add(
+                    pointer.clone(),
+                    format!(
+                        "The watched directory {:?} overlaps the managed directory {:?}.",
+                        watch_path, managed_path
+                    ),
+                );
+            }
+        }
+    }
+
+    let mut problems = problems.problems;
+    problems.sort_by_key(|problem| (problem.line, problem.column));
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("fsm_validate_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("watch")).unwrap();
+        fs::create_dir_all(dir.join("managed")).unwrap();
+        dir
+    }
+
+    fn validate(source: &str, format: ConfigFormat) -> Vec<ConfigProblem> {
+        let config = format.parse(source).unwrap();
+        validate_fsm_config(&config, source, format)
+    }
+
+    #[test]
+    fn locates_json_values() {
+        let source = r#"{
+  "managed_path": "/tmp/managed",
+  "filters": [
+    {"directory_key": "a"},
+    {"directory_key": "b", "filename_pattern": "x"}
+  ],
+  "a/b": {"c~d": 1}
+}"#;
+        let position = |pointer| get_position(source, locate(source, pointer));
+        assert_eq!(position(""), (1, 1));
+        assert_eq!(position("/managed_path"), (2, 19));
+        assert_eq!(position("/filters/1"), (5, 5));
+        assert_eq!(position("/filters/1/filename_pattern"), (5, 48));
+        assert_eq!(position("/a~1b/c~0d"), (7, 18));
+        // missing values resolve to the closest parent
+        assert_eq!(position("/filters/1/max_size"), (5, 5));
+        assert_eq!(position("/filters/7/directory_key"), (3, 14));
+        assert_eq!(position("/watch_path"), (1, 1));
+    }
+
+    #[test]
+    fn reports_config_problems() {
+        let dir = test_dir("problems");
+        let source = serde_json::json!({
+            "watch_path": dir.join("watch"),
+            "managed_path": dir.join("managed"),
+            "managed_directory_structure": {
+                "docs": "documents",
+                "more": {"docs": "documents", "images": "images"}
+            },
+            "filters": [
+                {"directory_key": "music", "filename_pattern": "\\.mp3$"},
+                {"directory_key": "images", "filename_pattern": "(unclosed"},
+                {"directory_key": "images", "filename_pattern": ".*"},
+                {"directory_key": "documents"}
+            ]
+        })
+        .to_string();
+        let problems: Vec<String> = validate(&source, ConfigFormat::Json)
+            .into_iter()
+            .map(|problem| problem.pointer)
+            .collect();
+        assert_eq!(
+            problems,
+            [
+                "/managed_directory_structure/more/docs",
+                "/filters/0/directory_key",
+                "/filters/1/filename_pattern",
+                "/filters/3",
+            ]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn reports_missing_and_overlapping_paths() {
+        let dir = test_dir("paths");
+        let source = format!(
+            "watch_path = {:?}\nmanaged_path = {:?}\nfilters = []\n\n[managed_directory_structure]\ndocs = \"documents\"\n\n[[watch_roots]]\npath = {:?}\n",
+            dir.join("managed/inbox"),
+            dir.join("managed"),
+            dir.join("missing"),
+        );
+        fs::create_dir_all(dir.join("managed/inbox")).unwrap();
+        let problems = validate(&source, ConfigFormat::Toml);
+        let problems: Vec<(&str, usize)> = problems
+            .iter()
+            .map(|problem| (problem.pointer.as_str(), problem.line))
+            .collect();
+        assert_eq!(problems, [("/watch_path", 1), ("/watch_roots/0/path", 9)]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn locates_yaml_values() {
+        let dir = test_dir("yaml");
+        let source = format!(
+            "watch_path: {:?}\nmanaged_path: {:?}\nmanaged_directory_structure:\n  docs: documents\nfilters:\n  - filename_pattern: \"[\"\n    directory_key: documents\n  - {{directory_key: music}}\n",
+            dir.join("watch"),
+            dir.join("managed"),
+        );
+        let problems: Vec<(String, usize, usize)> = validate(&source, ConfigFormat::Yaml)
+            .into_iter()
+            .map(|problem| (problem.pointer, problem.line, problem.column))
+            .collect();
+        assert_eq!(
+            problems,
+            [
+                ("/filters/0/filename_pattern".to_string(), 6, 5),
+                ("/filters/1/directory_key".to_string(), 8, 6),
+            ]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn requires_something_to_watch() {
+        let dir = test_dir("nothing");
+        let source = serde_json::json!({
+            "managed_path": dir.join("managed"),
+            "managed_directory_structure": "documents",
+            "filters": []
+        })
+        .to_string();
+        let problems = validate(&source, ConfigFormat::Json);
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].pointer, "");
+        fs::remove_dir_all(dir).unwrap();
+    }
+}